@@ -0,0 +1,451 @@
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::primer::iupac_matches;
+
+/// Illumina TruSeq read 1 / read 2 adapters.
+pub const TRUSEQ_R1: &[u8] = b"AGATCGGAAGAGCACACGTCTGAACTCCAGTCA";
+pub const TRUSEQ_R2: &[u8] = b"AGATCGGAAGAGCGTCGTGTAGGGAAAGAGTGT";
+/// Nextera transposase adapter (same on both reads).
+pub const NEXTERA: &[u8] = b"CTGTCTCTTATACACATCT";
+/// Illumina small RNA 3' adapter.
+pub const SMALL_RNA: &[u8] = b"TGGAATTCTCGGGTGCCAAGG";
+
+/// Built-in adapter sets selectable with `--adapter-preset`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AdapterPreset {
+    Truseq,
+    Nextera,
+    SmallRna,
+}
+
+impl AdapterPreset {
+    /// Return the (R1, R2) adapter sequences for this preset.
+    pub fn sequences(self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            AdapterPreset::Truseq => (TRUSEQ_R1, TRUSEQ_R2),
+            AdapterPreset::Nextera => (NEXTERA, NEXTERA),
+            AdapterPreset::SmallRna => (SMALL_RNA, SMALL_RNA),
+        }
+    }
+}
+
+/// An adapter in cutadapt syntax, as given to `--adapter`/`--adapter2`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Adapter {
+    /// `ADAPTER`: 3' adapter anywhere in the read, or partial at its 3' end
+    Back(Vec<u8>),
+    /// `ADAPTER$`: 3' adapter that must occur in full at the very end of the read
+    AnchoredBack(Vec<u8>),
+    /// `^ADAPTER`: 5' adapter that must occur in full at the very start of the read
+    AnchoredFront(Vec<u8>),
+    /// `FRONT...BACK`: anchored 5' adapter plus a 3' adapter (`BACK` or
+    /// `BACK$`) that is only searched for once the 5' adapter is found
+    Linked { front: Vec<u8>, back: Box<Adapter> },
+}
+
+impl FromStr for Adapter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let seq = |part: &str| -> Result<Vec<u8>, String> {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_alphabetic()) {
+                return Err(format!("invalid adapter sequence '{}'", s));
+            }
+            Ok(part.as_bytes().to_ascii_uppercase())
+        };
+        if let Some((front, back)) = s.split_once("...") {
+            let front = seq(front.strip_prefix('^').unwrap_or(front))?;
+            let back = match back.strip_suffix('$') {
+                Some(b) => Adapter::AnchoredBack(seq(b)?),
+                None => Adapter::Back(seq(back)?),
+            };
+            return Ok(Adapter::Linked {
+                front,
+                back: Box::new(back),
+            });
+        }
+        if let Some(front) = s.strip_prefix('^') {
+            return Ok(Adapter::AnchoredFront(seq(front)?));
+        }
+        if let Some(back) = s.strip_suffix('$') {
+            return Ok(Adapter::AnchoredBack(seq(back)?));
+        }
+        Ok(Adapter::Back(seq(s)?))
+    }
+}
+
+impl Adapter {
+    /// Locate the adapter in `seq` and return the `(start, end)` range of the
+    /// read to keep, or None when the adapter is not found.
+    pub fn find(
+        &self,
+        seq: &[u8],
+        max_error_rate: f64,
+        min_overlap: usize,
+    ) -> Option<(usize, usize)> {
+        match self {
+            Adapter::Back(a) => {
+                find_3prime(seq, a, max_error_rate, min_overlap).map(|pos| (0, pos))
+            }
+            Adapter::AnchoredBack(a) => {
+                let start = seq.len().checked_sub(a.len())?;
+                matches_at(&seq[start..], a, max_error_rate).then_some((0, start))
+            }
+            Adapter::AnchoredFront(a) => (seq.len() >= a.len()
+                && matches_at(&seq[..a.len()], a, max_error_rate))
+            .then_some((a.len(), seq.len())),
+            Adapter::Linked { front, back } => {
+                if seq.len() < front.len()
+                    || !matches_at(&seq[..front.len()], front, max_error_rate)
+                {
+                    return None;
+                }
+                let start = front.len();
+                match back.find(&seq[start..], max_error_rate, min_overlap) {
+                    Some((_, end)) => Some((start, start + end)),
+                    None => Some((start, seq.len())),
+                }
+            }
+        }
+    }
+}
+
+/// Pick the R1/R2 adapters from explicit adapters and/or a preset.
+/// Explicit adapters take precedence; R2 falls back to the R1 adapter when only
+/// `adapter` is given.
+pub fn select_adapters(
+    adapter: Option<&Adapter>,
+    adapter2: Option<&Adapter>,
+    preset: Option<AdapterPreset>,
+) -> (Option<Adapter>, Option<Adapter>) {
+    let (p1, p2) = match preset {
+        Some(p) => {
+            let (a, b) = p.sequences();
+            (
+                Some(Adapter::Back(a.to_vec())),
+                Some(Adapter::Back(b.to_vec())),
+            )
+        }
+        None => (None, None),
+    };
+    let r1 = adapter.cloned().or(p1);
+    let r2 = adapter2.cloned().or(p2).or_else(|| r1.clone());
+    (r1, r2)
+}
+
+/// IUPAC codes in the adapter match any of their bases, as in cutadapt.
+fn bases_match(read: u8, adapter: u8) -> bool {
+    iupac_matches(read, adapter)
+}
+
+/// True when `part` matches `adapter` over its full length within the error rate.
+fn matches_at(part: &[u8], adapter: &[u8], max_error_rate: f64) -> bool {
+    let allowed = (adapter.len() as f64 * max_error_rate).floor() as usize;
+    part.iter()
+        .zip(adapter)
+        .filter(|(r, a)| !bases_match(**r, **a))
+        .count()
+        <= allowed
+}
+
+/// Find the start of a 3' adapter in `seq`.
+///
+/// The adapter may occur anywhere in the read, or only partially at the 3' end
+/// (read-through into the first bases of the adapter). A candidate position is
+/// accepted when the aligned part is at least `min_overlap` long and has no more
+/// than `floor(overlap * max_error_rate)` mismatches. IUPAC codes in the
+/// adapter (`N`, `W`, `M`, ...) match any of their bases. Returns the leftmost
+/// accepted position.
+pub fn find_3prime(
+    seq: &[u8],
+    adapter: &[u8],
+    max_error_rate: f64,
+    min_overlap: usize,
+) -> Option<usize> {
+    if adapter.is_empty() {
+        return None;
+    }
+    let min_overlap = min_overlap.max(1);
+    for i in 0..seq.len() {
+        let overlap = adapter.len().min(seq.len() - i);
+        if overlap < min_overlap {
+            break;
+        }
+        let allowed = (overlap as f64 * max_error_rate).floor() as usize;
+        let mut errors = 0usize;
+        for (r, a) in seq[i..i + overlap].iter().zip(adapter) {
+            if !bases_match(*r, *a) {
+                errors += 1;
+                if errors > allowed {
+                    break;
+                }
+            }
+        }
+        if errors <= allowed {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Default k-mer size used for adapter inference.
+pub const INFER_K: usize = 12;
+/// Longest adapter sequence `infer_adapter` will assemble.
+const INFER_MAX_LEN: usize = 64;
+/// Fraction of covering reads that must agree on a base to extend the adapter.
+const INFER_MIN_AGREEMENT: f64 = 0.6;
+/// Minimum number of covering reads needed to extend the adapter by one base.
+const INFER_MIN_COVERAGE: usize = 3;
+/// A seed must occur in at least this many reads, and in at least 0.5% of them.
+const INFER_MIN_SUPPORT: usize = 5;
+
+/// Adapter assembled by `infer_adapter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InferredAdapter {
+    pub seq: Vec<u8>,
+    /// Number of sampled reads containing the seed k-mer anywhere.
+    pub support: usize,
+}
+
+/// Reject homopolymer/dinucleotide-like k-mers (e.g. poly-G tails) as seeds.
+fn is_complex(kmer: &[u8]) -> bool {
+    let mut distinct = [false; 256];
+    for b in kmer {
+        distinct[*b as usize] = true;
+    }
+    distinct.iter().filter(|d| **d).count() >= 3 && !kmer.contains(&b'N')
+}
+
+/// Infer a 3' adapter from a sample of read sequences.
+///
+/// k-mers from the 3' half of each read are counted (once per read). The most
+/// frequent one, if present in enough reads to be over-represented, seeds the adapter,
+/// which is then extended base by base in both directions using the consensus
+/// of the reads containing the seed. Extension towards the 5' end stops where
+/// the inserts start to disagree, i.e. at the adapter start.
+pub fn infer_adapter(reads: &[Vec<u8>], k: usize) -> Option<InferredAdapter> {
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    for r in reads {
+        if r.len() < k {
+            continue;
+        }
+        let mut seen: HashSet<&[u8]> = HashSet::new();
+        for i in r.len() / 2..=r.len() - k {
+            let kmer = &r[i..i + k];
+            if is_complex(kmer) && seen.insert(kmer) {
+                *counts.entry(kmer).or_insert(0) += 1;
+            }
+        }
+    }
+    let (seed, support) = counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))?;
+    if support < (reads.len() / 200).max(INFER_MIN_SUPPORT) {
+        return None;
+    }
+
+    // position of the seed in each read that contains it
+    let hits: Vec<(&[u8], usize)> = reads
+        .iter()
+        .filter_map(|r| {
+            r.windows(k)
+                .position(|w| w == seed)
+                .map(|p| (r.as_slice(), p))
+        })
+        .collect();
+
+    let consensus = |pick: &dyn Fn(&[u8], usize) -> Option<u8>| -> Option<u8> {
+        let mut tally = [0usize; 256];
+        let mut covered = 0usize;
+        for (r, p) in &hits {
+            if let Some(b) = pick(r, *p) {
+                tally[b as usize] += 1;
+                covered += 1;
+            }
+        }
+        if covered < INFER_MIN_COVERAGE {
+            return None;
+        }
+        let (base, n) = tally.iter().enumerate().max_by_key(|(_, n)| **n)?;
+        if (*n as f64) < covered as f64 * INFER_MIN_AGREEMENT || base == b'N' as usize {
+            return None;
+        }
+        Some(base as u8)
+    };
+
+    let mut seq = seed.to_vec();
+    let mut right = 0usize;
+    while seq.len() < INFER_MAX_LEN {
+        match consensus(&|r: &[u8], p: usize| r.get(p + k + right).copied()) {
+            Some(b) => {
+                seq.push(b);
+                right += 1;
+            }
+            None => break,
+        }
+    }
+    let mut left = 0usize;
+    while seq.len() < INFER_MAX_LEN {
+        match consensus(&|r: &[u8], p: usize| p.checked_sub(left + 1).map(|i| r[i])) {
+            Some(b) => {
+                seq.insert(0, b);
+                left += 1;
+            }
+            None => break,
+        }
+    }
+
+    Some(InferredAdapter {
+        seq,
+        support: hits.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_3prime, infer_adapter, select_adapters, Adapter, AdapterPreset, INFER_K, NEXTERA,
+        TRUSEQ_R1, TRUSEQ_R2,
+    };
+
+    #[test]
+    fn finds_full_adapter_inside_read() {
+        let mut seq = b"ACGTACGTACGTACGTACGT".to_vec();
+        seq.extend_from_slice(TRUSEQ_R1);
+        seq.extend_from_slice(b"AAAA");
+        assert_eq!(find_3prime(&seq, TRUSEQ_R1, 0.1, 3), Some(20));
+    }
+
+    #[test]
+    fn finds_partial_adapter_at_read_end() {
+        let mut seq = b"TTTTCCCCGGGGTTTTCCCC".to_vec();
+        seq.extend_from_slice(&TRUSEQ_R1[..5]);
+        assert_eq!(find_3prime(&seq, TRUSEQ_R1, 0.1, 3), Some(20));
+        // below the minimum overlap nothing is reported
+        assert_eq!(find_3prime(&seq, TRUSEQ_R1, 0.1, 6), None);
+    }
+
+    #[test]
+    fn tolerates_mismatches_up_to_error_rate() {
+        let mut adapter = TRUSEQ_R1.to_vec();
+        adapter[3] = b'A'; // T -> A
+        adapter[10] = b'C'; // G -> C
+        let mut seq = b"CCCCCCCCCC".to_vec();
+        seq.extend_from_slice(&adapter);
+        // 2 errors over 33 bases: allowed at 10%, rejected at 5%
+        assert_eq!(find_3prime(&seq, TRUSEQ_R1, 0.1, 3), Some(10));
+        assert_eq!(find_3prime(&seq, TRUSEQ_R1, 0.05, 3), None);
+    }
+
+    #[test]
+    fn explicit_adapter_overrides_preset() {
+        let custom: Adapter = "acgtacgt".parse().unwrap();
+        let (r1, r2) = select_adapters(Some(&custom), None, Some(AdapterPreset::Truseq));
+        assert_eq!(r1.unwrap(), Adapter::Back(b"ACGTACGT".to_vec()));
+        assert_eq!(r2.unwrap(), Adapter::Back(TRUSEQ_R2.to_vec()));
+
+        let (r1, r2) = select_adapters(Some(&custom), None, None);
+        assert_eq!(r1, r2);
+    }
+
+    #[test]
+    fn parses_cutadapt_adapter_syntax() {
+        assert_eq!(
+            "ACGT$".parse::<Adapter>().unwrap(),
+            Adapter::AnchoredBack(b"ACGT".to_vec())
+        );
+        assert_eq!(
+            "^ACGT".parse::<Adapter>().unwrap(),
+            Adapter::AnchoredFront(b"ACGT".to_vec())
+        );
+        assert_eq!(
+            "^AAAA...CCCC$".parse::<Adapter>().unwrap(),
+            Adapter::Linked {
+                front: b"AAAA".to_vec(),
+                back: Box::new(Adapter::AnchoredBack(b"CCCC".to_vec())),
+            }
+        );
+        assert!("ACGT...".parse::<Adapter>().is_err());
+        assert!("AC GT".parse::<Adapter>().is_err());
+    }
+
+    #[test]
+    fn anchored_adapters_only_match_at_read_ends() {
+        let front: Adapter = "^GATTACA".parse().unwrap();
+        assert_eq!(front.find(b"GATTACATTTTCCCC", 0.0, 3), Some((7, 15)));
+        assert_eq!(front.find(b"TGATTACATTTCCCC", 0.0, 3), None);
+
+        let back: Adapter = "GATTACA$".parse().unwrap();
+        assert_eq!(back.find(b"TTTTCCCCGATTACA", 0.0, 3), Some((0, 8)));
+        // a partial adapter at the end is not enough for an anchored 3' adapter
+        assert_eq!(back.find(b"TTTTCCCCCCGATTA", 0.0, 3), None);
+    }
+
+    #[test]
+    fn linked_adapter_needs_front_before_back() {
+        let linked: Adapter = "GATTACA...TGGAATTC".parse().unwrap();
+        let read = b"GATTACACCCCCCCCCCTGGAATTCAA";
+        assert_eq!(linked.find(read, 0.0, 3), Some((7, 17)));
+        // front only: 5' part removed, no 3' adapter in the read
+        assert_eq!(linked.find(b"GATTACACCCCCCCCCC", 0.0, 3), Some((7, 17)));
+        // without the front the 3' adapter is ignored
+        assert_eq!(linked.find(b"ACCCCCCCCCCTGGAATTCAA", 0.0, 3), None);
+    }
+
+    #[test]
+    fn degenerate_linked_adapter_matches_iupac_codes() {
+        // 515F/806R from the README; the read has one real mismatch in the
+        // front (GTGA...) besides the resolved degenerate positions
+        let linked: Adapter = "^GTGCCAGCMGCCGCGGTAA...ATTAGAWACCCBNGTAGTCC"
+            .parse()
+            .unwrap();
+        let insert = b"TACGGAGGGTGCAAGCGTTAATCGGAATTACTGGGCGTAAAG";
+        let mut read = b"GTGACAGCAGCCGCGGTAA".to_vec();
+        read.extend_from_slice(insert);
+        read.extend_from_slice(b"ATTAGATACCCTGGTAGTCC");
+        assert_eq!(linked.find(&read, 0.1, 3), Some((19, 19 + insert.len())));
+    }
+
+    /// Deterministic pseudo-random sequence (splitmix64) so tests don't need `rand`.
+    fn insert(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_add(0x9e3779b97f4a7c15);
+                let mut z = x;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+                b"ACGT"[((z ^ (z >> 31)) % 4) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn infers_adapter_from_read_through() {
+        let read_len = 80;
+        let mut reads = Vec::new();
+        for i in 0..200u64 {
+            if i % 4 == 0 {
+                // short insert reading through the Nextera adapter into random sequence
+                let mut r = insert(i, 30 + (i as usize % 10));
+                r.extend_from_slice(NEXTERA);
+                let pad = read_len - r.len();
+                r.extend(insert(i + 1000, pad));
+                reads.push(r);
+            } else {
+                reads.push(insert(i, read_len));
+            }
+        }
+        let inferred = infer_adapter(&reads, INFER_K).unwrap();
+        assert_eq!(inferred.seq, NEXTERA.to_vec());
+        assert_eq!(inferred.support, 50);
+    }
+
+    #[test]
+    fn no_adapter_in_random_reads() {
+        let reads: Vec<Vec<u8>> = (0..200u64).map(|i| insert(i, 80)).collect();
+        assert_eq!(infer_adapter(&reads, INFER_K), None);
+    }
+}
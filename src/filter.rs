@@ -0,0 +1,269 @@
+use clap::ValueEnum;
+use std::str::FromStr;
+
+/// Error probability of a Phred+33 quality character.
+fn error_prob(q: u8) -> f64 {
+    10f64.powf(-(q.saturating_sub(33) as f64) / 10.0)
+}
+
+/// Expected number of errors in a read: the sum of 10^(-Q/10) over its bases.
+pub fn expected_errors(qual: &[u8]) -> f64 {
+    qual.iter().map(|q| error_prob(*q)).sum()
+}
+
+/// Length of the longest prefix whose cumulative expected errors stay within
+/// `max_ee` (the read is truncated at the base where the limit is passed).
+pub fn truncate_ee(qual: &[u8], max_ee: f64) -> usize {
+    let mut ee = 0f64;
+    for (i, q) in qual.iter().enumerate() {
+        ee += error_prob(*q);
+        if ee > max_ee {
+            return i;
+        }
+    }
+    qual.len()
+}
+
+/// Mean Phred score of a read (0 for an empty read).
+pub fn mean_quality(qual: &[u8]) -> f64 {
+    if qual.is_empty() {
+        return 0.0;
+    }
+    qual.iter()
+        .map(|q| q.saturating_sub(33) as f64)
+        .sum::<f64>()
+        / qual.len() as f64
+}
+
+/// Percentage of bases with a Phred score below `min_qual` (fastp's
+/// "unqualified" bases).
+pub fn unqualified_percent(qual: &[u8], min_qual: u8) -> f64 {
+    if qual.is_empty() {
+        return 0.0;
+    }
+    let low = qual
+        .iter()
+        .filter(|q| q.saturating_sub(33) < min_qual)
+        .count();
+    low as f64 * 100.0 / qual.len() as f64
+}
+
+/// Number of ambiguous (N) bases in a read.
+pub fn count_n(seq: &[u8]) -> usize {
+    seq.iter().filter(|b| b.eq_ignore_ascii_case(&b'N')).count()
+}
+
+/// Read-length ranges for `--length-bins`, split at increasing boundaries:
+/// `50,100` gives the bins 0-49, 50-99 and 100+.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LengthBins {
+    bounds: Vec<usize>,
+}
+
+impl FromStr for LengthBins {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bounds = s
+            .split(',')
+            .map(|v| {
+                v.trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid length bin boundary '{}'", v))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if bounds[0] == 0 || bounds.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("length bin boundaries must be positive and increasing".to_string());
+        }
+        Ok(LengthBins { bounds })
+    }
+}
+
+impl LengthBins {
+    /// Index of the bin holding a read of length `len`.
+    pub fn bin(&self, len: usize) -> usize {
+        self.bounds.iter().filter(|b| len >= **b).count()
+    }
+
+    /// Labels of all bins in order, e.g. `0-49`, `50-99`, `100+`.
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels = Vec::with_capacity(self.bounds.len() + 1);
+        let mut low = 0;
+        for b in &self.bounds {
+            labels.push(format!("{}-{}", low, b - 1));
+            low = *b;
+        }
+        labels.push(format!("{}+", low));
+        labels
+    }
+}
+
+/// Low-complexity scoring method selectable with `--complexity`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ComplexityMethod {
+    /// Normalised trinucleotide Shannon entropy (0-1); low values fail
+    Entropy,
+    /// DUST score (0-100); high values fail
+    Dust,
+}
+
+impl ComplexityMethod {
+    /// Threshold used when `--complexity-threshold` is not given.
+    pub fn default_threshold(self) -> f64 {
+        match self {
+            ComplexityMethod::Entropy => 0.5,
+            ComplexityMethod::Dust => 7.0,
+        }
+    }
+
+    /// True when `seq` is low complexity at `threshold`.
+    pub fn is_low(self, seq: &[u8], threshold: f64) -> bool {
+        match self {
+            ComplexityMethod::Entropy => entropy(seq) < threshold,
+            ComplexityMethod::Dust => dust_score(seq) > threshold,
+        }
+    }
+}
+
+/// Which mates must fail the complexity filter to drop a pair.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PairFilter {
+    /// Drop the pair if either mate fails
+    #[default]
+    Either,
+    /// Drop the pair only if both mates fail
+    Both,
+}
+
+/// Counts of the 64 ACGT trinucleotides in `seq` (triplets with other bases are
+/// skipped) and the number of triplets counted.
+fn triplet_counts(seq: &[u8]) -> ([u32; 64], u32) {
+    let code = |b: u8| match b.to_ascii_uppercase() {
+        b'A' => Some(0usize),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    };
+    let mut counts = [0u32; 64];
+    let mut total = 0u32;
+    for w in seq.windows(3) {
+        if let (Some(a), Some(b), Some(c)) = (code(w[0]), code(w[1]), code(w[2])) {
+            counts[a * 16 + b * 4 + c] += 1;
+            total += 1;
+        }
+    }
+    (counts, total)
+}
+
+/// Shannon entropy of the trinucleotide composition, normalised to 0-1 by the
+/// largest entropy possible for the number of triplets (as in PRINSEQ).
+/// Reads with fewer than two triplets score 1.
+pub fn entropy(seq: &[u8]) -> f64 {
+    let (counts, total) = triplet_counts(seq);
+    if total < 2 {
+        return 1.0;
+    }
+    let n = total as f64;
+    let h: f64 = counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / n;
+            -p * p.ln()
+        })
+        .sum();
+    h / (total.min(64) as f64).ln()
+}
+
+/// DUST score: sum of c*(c-1)/2 over trinucleotide counts divided by (triplets - 1),
+/// scaled to 0-100 (PRINSEQ scaling; a homopolymer scores 100).
+/// Reads with fewer than two triplets score 0.
+pub fn dust_score(seq: &[u8]) -> f64 {
+    let (counts, total) = triplet_counts(seq);
+    if total < 2 {
+        return 0.0;
+    }
+    let sum: f64 = counts
+        .iter()
+        .map(|c| *c as f64 * (*c as f64 - 1.0) / 2.0)
+        .sum();
+    let score = sum / (total as f64 - 1.0);
+    (score * 100.0 / 31.0).min(100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        count_n, dust_score, entropy, expected_errors, mean_quality, truncate_ee,
+        unqualified_percent, ComplexityMethod, LengthBins,
+    };
+
+    #[test]
+    fn expected_errors_sums_error_probabilities() {
+        // Q10 -> 0.1, Q20 -> 0.01, Q30 -> 0.001
+        let qual = [10u8 + 33, 20 + 33, 30 + 33];
+        assert!((expected_errors(&qual) - 0.111).abs() < 1e-9);
+        assert_eq!(expected_errors(&[]), 0.0);
+    }
+
+    #[test]
+    fn truncates_where_cumulative_ee_passes_limit() {
+        // ten Q20 bases then Q2 bases (0.63 each)
+        let mut qual = vec![20u8 + 33; 10];
+        qual.extend(vec![2u8 + 33; 5]);
+        assert_eq!(truncate_ee(&qual, 0.5), 10);
+        assert_eq!(truncate_ee(&qual, 100.0), 15);
+        assert_eq!(truncate_ee(&qual, 0.0), 0);
+    }
+
+    #[test]
+    fn mean_and_unqualified_percent() {
+        let qual = [10u8 + 33, 20 + 33, 30 + 33, 40 + 33];
+        assert!((mean_quality(&qual) - 25.0).abs() < 1e-9);
+        assert_eq!(mean_quality(&[]), 0.0);
+        assert!((unqualified_percent(&qual, 15) - 25.0).abs() < 1e-9);
+        assert!((unqualified_percent(&qual, 31) - 75.0).abs() < 1e-9);
+        assert_eq!(unqualified_percent(&qual, 0), 0.0);
+    }
+
+    #[test]
+    fn length_bins_split_at_boundaries() {
+        let bins: LengthBins = "50,100".parse().unwrap();
+        assert_eq!(bins.labels(), vec!["0-49", "50-99", "100+"]);
+        assert_eq!(bins.bin(18), 0);
+        assert_eq!(bins.bin(50), 1);
+        assert_eq!(bins.bin(99), 1);
+        assert_eq!(bins.bin(150), 2);
+        assert!("100,50".parse::<LengthBins>().is_err());
+        assert!("0,50".parse::<LengthBins>().is_err());
+        assert!("20,x".parse::<LengthBins>().is_err());
+    }
+
+    #[test]
+    fn counts_ambiguous_bases() {
+        assert_eq!(count_n(b"ACNNGTn"), 3);
+        assert_eq!(count_n(b"ACGT"), 0);
+    }
+
+    #[test]
+    fn repeats_score_low_complexity() {
+        let homopolymer = vec![b'A'; 64];
+        let dinucleotide = b"AC".repeat(32);
+        let random = b"ACGGTCATTGCAGTCCGATAGGCTTACGATCGGATCCATGACTGACTTAGCCATGCAATCGGTA";
+
+        assert_eq!(entropy(&homopolymer), 0.0);
+        assert!(entropy(&dinucleotide) < 0.2);
+        assert!(entropy(random) > 0.8);
+
+        assert!((dust_score(&homopolymer) - 100.0).abs() < 1e-9);
+        assert!(dust_score(&dinucleotide) > 7.0);
+        assert!(dust_score(random) < 7.0);
+
+        let entropy_method = ComplexityMethod::Entropy;
+        let t = entropy_method.default_threshold();
+        assert!(entropy_method.is_low(&dinucleotide, t));
+        assert!(!entropy_method.is_low(random, t));
+        assert!(ComplexityMethod::Dust.is_low(&homopolymer, 7.0));
+    }
+}
@@ -1,83 +1,284 @@
+use bio::io::fastq;
+use bzip2::read::MultiBzDecoder;
 use flate2::read::MultiGzDecoder;
+use flate2::Compression;
+use rayon::ThreadPool;
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::write::Encoder as ZstdEncoder;
 
+use crate::pgzip::ParGzWriter;
+
+/// Open a FASTQ input file, or stdin for `-`, decompressing gzip, zstd,
+/// bzip2 or xz input detected from its magic bytes.
 pub fn open_input(path: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
     if path == "-" {
-        let mut br = BufReader::new(io::stdin());
-        let buf = br.fill_buf()?;
-        let is_gz = buf.len() >= 2 && buf[0] == 0x1f && buf[1] == 0x8b;
-        let _ = buf;
-        if is_gz {
-            Ok(Box::new(MultiGzDecoder::new(br)))
-        } else {
-            Ok(Box::new(br))
-        }
+        Ok(decoder(BufReader::new(io::stdin()))?)
     } else {
-        let f = File::open(path)?;
-        let mut br = BufReader::new(f);
-        let buf = br.fill_buf()?;
-        let is_gz = buf.len() >= 2 && buf[0] == 0x1f && buf[1] == 0x8b;
-        let _ = buf;
-        if is_gz {
-            Ok(Box::new(MultiGzDecoder::new(br)))
-        } else {
-            Ok(Box::new(br))
-        }
+        Ok(decoder(BufReader::new(File::open(path)?))?)
     }
 }
 
-/// Given an output base name and gzip flag, return file paths for R1, R2 and singletons.
-pub fn make_output_files(base: &str, gz: bool, zstd: bool) -> (String, String, String) {
-    if gz {
-        (
-            format!("{}{}_R1.fastq.gz", base, ""),
-            format!("{}{}_R2.fastq.gz", base, ""),
-            format!("{}{}_singletons.fastq.gz", base, ""),
-        )
-    } else if zstd {
-        (
-            format!("{}{}_R1.fastq.zst", base, ""),
-            format!("{}{}_R2.fastq.zst", base, ""),
-            format!("{}{}_singletons.fastq.zst", base, ""),
-        )
+/// Wrap `br` in the decoder matching its first bytes; plain text is passed
+/// through. Concatenated streams (multi-member gzip, pbzip2, ...) are read in full.
+fn decoder<R: BufRead + 'static>(mut br: R) -> io::Result<Box<dyn Read>> {
+    let buf = br.fill_buf()?;
+    let reader: Box<dyn Read> = if buf.starts_with(&[0x1f, 0x8b]) {
+        Box::new(MultiGzDecoder::new(br))
+    } else if buf.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(ZstdDecoder::with_buffer(br)?)
+    } else if buf.starts_with(b"BZh") {
+        Box::new(MultiBzDecoder::new(br))
+    } else if buf.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(XzDecoder::new_multi_decoder(br))
     } else {
-        (
-            format!("{}{}_R1.fastq", base, ""),
-            format!("{}{}_R2.fastq", base, ""),
-            format!("{}{}_singletons.fastq", base, ""),
-        )
-    }
-}
-
-/// Return plain (non-.zst) filenames when `zstd` is true, otherwise return the
-/// provided names cloned as owned `String`s.
-pub fn make_plain_filenames(
-    r1: &str,
-    r2: &str,
-    single: &str,
-    zstd: bool,
-) -> (String, String, String) {
-    if zstd {
-        (
-            r1.trim_end_matches(".zst").to_string(),
-            r2.trim_end_matches(".zst").to_string(),
-            single.trim_end_matches(".zst").to_string(),
-        )
-    } else {
-        (r1.to_string(), r2.to_string(), single.to_string())
+        Box::new(br)
+    };
+    Ok(reader)
+}
+
+/// Compression of output files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Plain,
+    /// Multi-member gzip at the given level
+    Gzip(u32),
+    /// BGZF at the given gzip level, with a `<path>.gzi` index when `index` is set
+    Bgzf {
+        level: u32,
+        index: bool,
+    },
+    /// zstd at the given level
+    Zstd(i32),
+}
+
+impl OutputFormat {
+    /// File name extension for outputs in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Plain => "",
+            OutputFormat::Gzip(_) | OutputFormat::Bgzf { .. } => ".gz",
+            OutputFormat::Zstd(_) => ".zst",
+        }
     }
 }
 
+/// Output file, plain or behind one of the compressing encoders.
+enum Output {
+    Plain(BufWriter<File>),
+    Gzip(ParGzWriter<BufWriter<File>>),
+    Zstd(ZstdEncoder<'static, BufWriter<File>>),
+}
+
+impl Write for Output {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(w) => w.write(data),
+            Output::Gzip(w) => w.write(data),
+            Output::Zstd(w) => w.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(w) => w.flush(),
+            Output::Gzip(w) => w.flush(),
+            Output::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+/// FASTQ writer for an output file made by `create_writer`.
+///
+/// `finish` must be called once all records are written: it writes the last
+/// compressed blocks (and the BGZF index) and returns any error from doing so.
+/// Dropping the writer unfinished still completes plain and gzip files, with
+/// errors lost, but leaves a zstd file truncated.
+pub struct FastqWriter {
+    writer: BufWriter<Output>,
+}
+
+impl FastqWriter {
+    /// Write a FASTQ record, as `bio::io::fastq::Writer::write` does.
+    pub fn write(
+        &mut self,
+        id: &str,
+        desc: Option<&str>,
+        seq: &[u8],
+        qual: &[u8],
+    ) -> io::Result<()> {
+        self.writer.write_all(b"@")?;
+        self.writer.write_all(id.as_bytes())?;
+        if let Some(desc) = desc {
+            self.writer.write_all(b" ")?;
+            self.writer.write_all(desc.as_bytes())?;
+        }
+        self.writer.write_all(b"\n")?;
+        self.writer.write_all(seq)?;
+        self.writer.write_all(b"\n+\n")?;
+        self.writer.write_all(qual)?;
+        self.writer.write_all(b"\n")
+    }
+
+    /// Complete the file and flush it to disk.
+    pub fn finish(self) -> io::Result<()> {
+        match self.writer.into_inner().map_err(|e| e.into_error())? {
+            Output::Plain(mut w) => w.flush(),
+            Output::Gzip(w) => w.finish().map(|_| ()),
+            Output::Zstd(w) => w.finish()?.flush(),
+        }
+    }
+}
+
+/// Create an output file, wrapped in a gzip, BGZF or zstd encoder as
+/// requested. Gzip and BGZF blocks are compressed in parallel on `pool`.
+pub fn create_writer(
+    path: &str,
+    format: OutputFormat,
+    pool: &Arc<ThreadPool>,
+) -> Result<FastqWriter, Box<dyn Error>> {
+    let f = BufWriter::new(File::create(path)?);
+    let output = match format {
+        OutputFormat::Plain => Output::Plain(f),
+        OutputFormat::Gzip(level) => Output::Gzip(ParGzWriter::new(
+            f,
+            Compression::new(level),
+            Arc::clone(pool),
+        )),
+        OutputFormat::Bgzf { level, index } => {
+            let gzi: Option<Box<dyn Write>> = if index {
+                let name = format!("{}.gzi", path);
+                Some(Box::new(BufWriter::new(File::create(name)?)))
+            } else {
+                None
+            };
+            Output::Gzip(ParGzWriter::bgzf(
+                f,
+                Compression::new(level),
+                Arc::clone(pool),
+                gzi,
+            ))
+        }
+        OutputFormat::Zstd(level) => Output::Zstd(ZstdEncoder::new(f, level)?),
+    };
+    Ok(FastqWriter {
+        writer: BufWriter::new(output),
+    })
+}
+
+/// Read the sequences of the first `n` records of a FASTQ file (plain or compressed).
+pub fn sample_sequences(path: &str, n: usize) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let fq = fastq::Reader::new(BufReader::new(open_input(path)?));
+    let mut seqs = Vec::with_capacity(n);
+    for result in fq.records().take(n) {
+        seqs.push(result?.seq().to_vec());
+    }
+    Ok(seqs)
+}
+
+/// Read the quality strings of the first `n` records of a FASTQ file (plain or compressed).
+pub fn sample_qualities(path: &str, n: usize) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let fq = fastq::Reader::new(BufReader::new(open_input(path)?));
+    let mut quals = Vec::with_capacity(n);
+    for result in fq.records().take(n) {
+        quals.push(result?.qual().to_vec());
+    }
+    Ok(quals)
+}
+
+/// Most bytes of stdin read ahead by `sample_stdin_qualities`.
+const STDIN_SAMPLE_BYTES: u64 = 8 << 20;
+
+/// An input read ahead to sample its first records.
+pub struct SampledInput {
+    /// Quality strings of the sampled records
+    pub quals: Vec<Vec<u8>>,
+    /// The whole input, sampled bytes included
+    pub reader: Box<dyn Read>,
+}
+
+/// Open stdin and read the quality strings of its first `n` records, as
+/// `sample_qualities` does for files. Stdin can only be read once, so the
+/// returned reader replays the sampled bytes before the rest of the input.
+pub fn sample_stdin_qualities(n: usize) -> Result<SampledInput, Box<dyn Error>> {
+    Ok(sample_stream_qualities(
+        open_input("-")?,
+        n,
+        STDIN_SAMPLE_BYTES,
+    )?)
+}
+
+/// Buffer up to `limit` bytes of `input` and read the quality strings of the
+/// records in them, at most `n`.
+fn sample_stream_qualities<R: Read + 'static>(
+    mut input: R,
+    n: usize,
+    limit: u64,
+) -> io::Result<SampledInput> {
+    let mut head = Vec::new();
+    input.by_ref().take(limit).read_to_end(&mut head)?;
+    // the last buffered record may be cut short; its quality prefix is still usable
+    let quals = fastq::Reader::new(&head[..])
+        .records()
+        .take(n)
+        .map_while(Result::ok)
+        .map(|rec| rec.qual().to_vec())
+        .collect();
+    Ok(SampledInput {
+        quals,
+        reader: Box::new(io::Cursor::new(head).chain(input)),
+    })
+}
+
+/// Insert `suffix` into an output file name before its extensions
+/// (`out.fastq.gz` -> `out<suffix>.fastq.gz`); names without one get it appended.
+pub fn insert_suffix(path: &str, suffix: &str) -> String {
+    let name_start = path.rfind('/').map_or(0, |i| i + 1);
+    match path[name_start..].find('.') {
+        Some(dot) if dot > 0 => {
+            let at = name_start + dot;
+            format!("{}{}{}", &path[..at], suffix, &path[at..])
+        }
+        _ => format!("{}{}", path, suffix),
+    }
+}
+
+/// Given an output base name and output format, return file paths for R1, R2 and singletons.
+pub fn make_output_files(base: &str, format: OutputFormat) -> (String, String, String) {
+    let ext = format.extension();
+    (
+        format!("{}_R1.fastq{}", base, ext),
+        format!("{}_R2.fastq{}", base, ext),
+        format!("{}_singletons.fastq{}", base, ext),
+    )
+}
+
+/// File path for merged read pairs next to the `make_output_files` outputs.
+pub fn make_merged_file(base: &str, format: OutputFormat) -> String {
+    format!("{}_merged.fastq{}", base, format.extension())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::open_input;
+    use super::{decoder, insert_suffix, open_input, sample_stream_qualities, OutputFormat};
     use flate2::write::GzEncoder;
     use flate2::Compression;
     use std::io::{Read, Write};
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn suffix_goes_before_extensions() {
+        assert_eq!(
+            insert_suffix("tests/out.fastq.gz", "_len0-49"),
+            "tests/out_len0-49.fastq.gz"
+        );
+        assert_eq!(insert_suffix("./run.1/out", "_x"), "./run.1/out_x");
+    }
+
     #[test]
     fn open_plain_file_reads_contents() -> Result<(), Box<dyn std::error::Error>> {
         let mut tmp = NamedTempFile::new()?;
@@ -131,9 +332,98 @@ mod tests {
         Ok(())
     }
 
+    /// Write `text` compressed by `encode` to a temp file and read it back
+    /// through `open_input`.
+    fn read_back(
+        encode: impl FnOnce(std::fs::File) -> std::io::Result<()>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let tmp = NamedTempFile::new()?;
+        let path = tmp.path().to_str().unwrap().to_string();
+        encode(std::fs::File::create(&path)?)?;
+
+        let mut reader = open_input(&path)?;
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn open_zstd_file_reads_decompressed() -> Result<(), Box<dyn std::error::Error>> {
+        let buf = read_back(|f| {
+            let mut zst = zstd::Encoder::new(f, 3)?;
+            write!(zst, "hello-zstd")?;
+            zst.finish().map(|_| ())
+        })?;
+        assert_eq!(buf, "hello-zstd");
+        Ok(())
+    }
+
+    #[test]
+    fn open_bzip2_file_reads_decompressed() -> Result<(), Box<dyn std::error::Error>> {
+        let buf = read_back(|f| {
+            let mut bz = bzip2::write::BzEncoder::new(f, bzip2::Compression::default());
+            write!(bz, "hello-bzip2")?;
+            bz.finish().map(|_| ())
+        })?;
+        assert_eq!(buf, "hello-bzip2");
+        Ok(())
+    }
+
+    #[test]
+    fn open_xz_file_reads_decompressed() -> Result<(), Box<dyn std::error::Error>> {
+        let buf = read_back(|f| {
+            let mut xz = xz2::write::XzEncoder::new(f, 6);
+            write!(xz, "hello-xz")?;
+            xz.finish().map(|_| ())
+        })?;
+        assert_eq!(buf, "hello-xz");
+        Ok(())
+    }
+
+    #[test]
+    fn stream_decoder_detects_each_format() -> Result<(), Box<dyn std::error::Error>> {
+        // the stdin path: sniff an in-memory stream rather than a file
+        let mut streams: Vec<Vec<u8>> = vec![b"hello".to_vec()];
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(b"hello")?;
+        streams.push(gz.finish()?);
+        streams.push(zstd::encode_all(&b"hello"[..], 3)?);
+        let mut bz = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz.write_all(b"hello")?;
+        streams.push(bz.finish()?);
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(b"hello")?;
+        streams.push(xz.finish()?);
+
+        for data in streams {
+            // concatenated streams are read through to the end
+            let twice = [data.clone(), data].concat();
+            let mut reader = decoder(std::io::Cursor::new(twice))?;
+            let mut buf = String::new();
+            reader.read_to_string(&mut buf)?;
+            assert_eq!(buf, "hellohello");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sampled_stream_is_replayed_in_full() -> Result<(), Box<dyn std::error::Error>> {
+        let fastq = "@a\nACGT\n+\nhhhh\n@b\nACGT\n+\nBBBB\n@c\nACGT\n+\nIIII\n";
+        // the read-ahead stops inside the second record
+        let mut sampled = sample_stream_qualities(fastq.as_bytes(), 10, 20)?;
+        assert_eq!(sampled.quals, vec![b"hhhh".to_vec()]);
+        let mut buf = String::new();
+        sampled.reader.read_to_string(&mut buf)?;
+        assert_eq!(buf, fastq);
+
+        let sampled = sample_stream_qualities(fastq.as_bytes(), 2, 1 << 20)?;
+        assert_eq!(sampled.quals, vec![b"hhhh".to_vec(), b"BBBB".to_vec()]);
+        Ok(())
+    }
+
     #[test]
     fn make_output_files_gz() {
-        let (r1, r2, single) = super::make_output_files("output", true, false);
+        let (r1, r2, single) = super::make_output_files("output", OutputFormat::Gzip(6));
         assert_eq!(r1, "output_R1.fastq.gz");
         assert_eq!(r2, "output_R2.fastq.gz");
         assert_eq!(single, "output_singletons.fastq.gz");
@@ -141,31 +431,14 @@ mod tests {
 
     #[test]
     fn make_output_files_plain_and_zstd() {
-        let (r1, r2, single) = super::make_output_files("out", false, false);
+        let (r1, r2, single) = super::make_output_files("out", OutputFormat::Plain);
         assert_eq!(r1, "out_R1.fastq");
         assert_eq!(r2, "out_R2.fastq");
         assert_eq!(single, "out_singletons.fastq");
 
-        let (zr1, zr2, zsingle) = super::make_output_files("out", false, true);
+        let (zr1, zr2, zsingle) = super::make_output_files("out", OutputFormat::Zstd(3));
         assert_eq!(zr1, "out_R1.fastq.zst");
         assert_eq!(zr2, "out_R2.fastq.zst");
         assert_eq!(zsingle, "out_singletons.fastq.zst");
     }
-
-    #[test]
-    fn make_plain_filenames_trims_zst() {
-        let r1 = "sample_R1.fastq.zst";
-        let r2 = "sample_R2.fastq.zst";
-        let s = "sample_singletons.fastq.zst";
-        let (p1, p2, ps) = super::make_plain_filenames(r1, r2, s, true);
-        assert_eq!(p1, "sample_R1.fastq");
-        assert_eq!(p2, "sample_R2.fastq");
-        assert_eq!(ps, "sample_singletons.fastq");
-
-        // when zstd=false, names are cloned unchanged
-        let (c1, c2, cs) = super::make_plain_filenames(r1, r2, s, false);
-        assert_eq!(c1, r1.to_string());
-        assert_eq!(c2, r2.to_string());
-        assert_eq!(cs, s.to_string());
-    }
 }
@@ -1,33 +1,46 @@
 use bio::io::fastq;
+use clap::builder::ArgPredicate;
 use clap::Parser;
-use flate2::write::GzEncoder;
-use flate2::Compression;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::error::Error;
-use std::fs::File;
-use std::io::copy;
-use std::io::BufWriter;
-use std::io::{BufReader, Write};
+use std::io::BufReader;
+use std::sync::Arc;
 use std::time::Instant;
-use zstd::stream::write::Encoder as ZstdEncoder;
 
+pub mod adapter;
+pub mod filter;
 pub mod io_utils;
+pub mod overlap;
+pub mod pgzip;
+pub mod polyx;
+pub mod primer;
+pub mod quality;
+pub mod stats;
+pub mod steps;
 pub mod trim;
 
-use crate::io_utils::open_input;
-use crate::trim::trim_record;
+use crate::adapter::{Adapter, AdapterPreset};
+use crate::filter::{ComplexityMethod, LengthBins, PairFilter};
+use crate::io_utils::{open_input, OutputFormat};
+use crate::primer::PrimerSet;
+use crate::quality::{Encoding, QualBins, QualOutput};
+use crate::stats::{Histogram, TrimStats};
+use crate::steps::Pipeline;
+use crate::trim::{trim_record_ends, trim_record_mott, Crop, TrimMode};
 
 #[derive(Parser)]
 #[command(author, version, about = "Simple FASTQ quality trimmer: removes low-quality bases from read ends using sliding window approach", long_about = None)]
 pub struct Args {
-    /// Input FASTQ (use '-' for stdin). Supports .gz compressed files.
+    /// Input FASTQ (use '-' for stdin). Gzip, zstd, bzip2 and xz input is detected and decompressed.
     /// Provide either a single input or both `--p1` and `--p2` for paired-end files.
     pub input: Option<String>,
 
-    /// Paired-end R1 (e.g. sample_R1.fastq or .fastq.gz)
+    /// Paired-end R1 (e.g. sample_R1.fastq, .fastq.gz, .fastq.zst, .fastq.bz2 or .fastq.xz)
     #[arg(long)]
     pub p1: Option<String>,
 
-    /// Paired-end R2 (e.g. sample_R2.fastq or .fastq.gz)
+    /// Paired-end R2 (e.g. sample_R2.fastq, .fastq.gz, .fastq.zst, .fastq.bz2 or .fastq.xz)
     #[arg(long)]
     pub p2: Option<String>,
 
@@ -35,22 +48,269 @@ pub struct Args {
     #[arg(long, default_value_t = 20)]
     pub qual: u8,
 
+    /// Quality threshold for the 5' end; defaults to `--qual` (0 in `--trim-mode mott`,
+    /// as `cutadapt -q`); 0 disables 5' quality trimming
+    #[arg(long)]
+    pub qual_front: Option<u8>,
+
+    /// Quality threshold for the 3' end; defaults to `--qual`; 0 disables 3' quality trimming
+    #[arg(long)]
+    pub qual_back: Option<u8>,
+
+    /// Replace bases below this quality with N after trimming, keeping the read
+    /// length and qualities; combine with `--qual 0` to mask without end trimming
+    #[arg(long)]
+    pub mask_qual: Option<u8>,
+
+    /// Quality encoding of the input: `auto` detects it from the first reads,
+    /// `33` or `64` override the detection
+    #[arg(long, value_enum, default_value_t = Encoding::Auto)]
+    pub phred: Encoding,
+
+    /// Write Phred+64 input with Phred+33 qualities (output otherwise keeps the input encoding)
+    #[arg(long, default_value_t = false)]
+    pub to_phred33: bool,
+
+    /// Bin output qualities to shrink archives: `illumina8` for Illumina's 8-level
+    /// scheme, or custom `low-high:value` bins such as "0-9:5,10-29:20,30-:35"
+    #[arg(long)]
+    pub qual_bins: Option<QualBins>,
+
     /// Minimum length to keep a read after trimming; default 30
     #[arg(long, default_value_t = 30)]
     pub min_len: usize,
 
+    /// Drop reads longer than this after trimming
+    #[arg(long)]
+    pub max_len: Option<usize>,
+
+    /// Single-end mode: write reads to one output file per length range, split at
+    /// these comma-separated boundaries (e.g. "50,100" for 0-49, 50-99 and 100+);
+    /// files are named `<output>_len<range>.fastq.gz`
+    #[arg(long, conflicts_with_all = ["p1", "p2"])]
+    pub length_bins: Option<LengthBins>,
+
     /// Sliding window size for trimming; use 1 to check single-base quality (default)
     #[arg(long, default_value_t = 1)]
     pub window: usize,
 
+    /// Sliding window size for the 5' end; defaults to `--window`
+    #[arg(long)]
+    pub window_front: Option<usize>,
+
+    /// Sliding window size for the 3' end; defaults to `--window`
+    #[arg(long)]
+    pub window_back: Option<usize>,
+
+    /// Remove this many bases from the 5' end of each read (R1 in paired mode)
+    /// before any other trimming
+    #[arg(long, default_value_t = 0)]
+    pub head_crop: usize,
+
+    /// Remove this many bases from the 5' end of R2; defaults to `--head-crop`
+    #[arg(long)]
+    pub head_crop2: Option<usize>,
+
+    /// Remove this many bases from the 3' end of each read (R1 in paired mode)
+    /// before any other trimming
+    #[arg(long, default_value_t = 0)]
+    pub tail_crop: usize,
+
+    /// Remove this many bases from the 3' end of R2; defaults to `--tail-crop`
+    #[arg(long)]
+    pub tail_crop2: Option<usize>,
+
+    /// Cut reads (R1 in paired mode) down to at most this many bases, after the head/tail crop
+    #[arg(long)]
+    pub crop_to: Option<usize>,
+
+    /// Cut R2 down to at most this many bases; defaults to `--crop-to`
+    #[arg(long)]
+    pub crop_to2: Option<usize>,
+
+    /// Quality-trimming algorithm: `edge` stops at the first good base/window from
+    /// each end; `mott` is the BWA/cutadapt algorithm on the 3' end, run first as in `cutadapt -q <QUAL>`
+    #[arg(long, value_enum, default_value_t = TrimMode::Edge)]
+    pub trim_mode: TrimMode,
+
+    /// Trimmomatic-style steps run in order instead of `--trim-mode`/`--min-len`,
+    /// e.g. "LEADING:3 TRAILING:3 SLIDINGWINDOW:4:15 HEADCROP:10 MINLEN:36".
+    /// Supported: LEADING, TRAILING, SLIDINGWINDOW, HEADCROP, CROP, MINLEN
+    #[arg(long)]
+    pub steps: Option<Pipeline>,
+
+    /// Drop reads whose expected errors (sum of 10^(-Q/10)) after trimming exceed this
+    #[arg(long)]
+    pub max_ee: Option<f64>,
+
+    /// Drop reads whose expected errors per base after trimming exceed this
+    #[arg(long)]
+    pub max_ee_rate: Option<f64>,
+
+    /// Drop reads whose mean Phred score after trimming is below this
+    #[arg(long)]
+    pub min_mean_qual: Option<f64>,
+
+    /// Drop reads where more than this percentage of bases (after trimming) are
+    /// below `--unqualified-qual`
+    #[arg(long)]
+    pub max_unqualified_percent: Option<f64>,
+
+    /// Phred score below which a base counts as unqualified; default 15
+    #[arg(long, default_value_t = 15)]
+    pub unqualified_qual: u8,
+
+    /// Truncate reads at the base where cumulative expected errors pass this limit
+    #[arg(long)]
+    pub truncate_ee: Option<f64>,
+
+    /// Remove leading and trailing N's before quality trimming
+    #[arg(long, default_value_t = false)]
+    pub trim_n: bool,
+
+    /// Keep only the longest N-free segment of each read
+    #[arg(long, default_value_t = false)]
+    pub n_split: bool,
+
+    /// Drop reads with more than this many N's after trimming
+    #[arg(long)]
+    pub max_n: Option<usize>,
+
+    /// Drop reads whose fraction of N's after trimming exceeds this
+    #[arg(long)]
+    pub max_n_frac: Option<f64>,
+
+    /// Drop low-complexity reads after trimming using Shannon entropy or DUST
+    #[arg(long, value_enum)]
+    pub complexity: Option<ComplexityMethod>,
+
+    /// Complexity threshold: entropy below it (default 0.5) or DUST above it
+    /// (default 7) fails
+    #[arg(long)]
+    pub complexity_threshold: Option<f64>,
+
+    /// Paired mode: drop the pair if `either` mate or only if `both` mates fail
+    /// the complexity filter
+    #[arg(long, value_enum, default_value_t = PairFilter::Either)]
+    pub complexity_pair: PairFilter,
+
+    /// Adapter to trim from R1 (and single-end reads), in cutadapt syntax:
+    /// `ADAPTER` (3'), `ADAPTER$` (anchored 3'), `^ADAPTER` (anchored 5') or
+    /// `FRONT...BACK` (linked: the 3' part is only searched once the 5' part is found)
+    #[arg(long)]
+    pub adapter: Option<Adapter>,
+
+    /// Adapter to trim from R2, same syntax as `--adapter`; defaults to the R1 adapter
+    #[arg(long)]
+    pub adapter2: Option<Adapter>,
+
+    /// Built-in adapter set; `--adapter`/`--adapter2` override it
+    #[arg(long, value_enum)]
+    pub adapter_preset: Option<AdapterPreset>,
+
+    /// Maximum fraction of mismatches allowed in an adapter match; default 0.1
+    #[arg(long, default_value_t = 0.1)]
+    pub adapter_error_rate: f64,
+
+    /// Minimum overlap between read end and adapter to trim; default 3
+    #[arg(long, default_value_t = 3)]
+    pub adapter_min_overlap: usize,
+
+    /// Infer the 3' adapter(s) from the first reads of the input(s) and trim with it
+    #[arg(long, default_value_t = false, conflicts_with_all = ["adapter", "adapter2", "adapter_preset"])]
+    pub adapter_auto: bool,
+
+    /// Number of leading reads sampled by `--adapter-auto`; default 10000
+    #[arg(long, default_value_t = 10000)]
+    pub adapter_auto_reads: usize,
+
+    /// Trim 3' poly-G tails; enabled automatically for two-colour instruments
+    /// (NovaSeq/NextSeq/...) detected from the read header
+    #[arg(long, default_value_t = false)]
+    pub poly_g: bool,
+
+    /// Never trim poly-G tails, even for two-colour instruments
+    #[arg(long, default_value_t = false, conflicts_with = "poly_g")]
+    pub no_poly_g: bool,
+
+    /// Trim 3' homopolymer tails of any base (A/C/G/T), after adapter trimming
+    #[arg(long, default_value_t = false)]
+    pub poly_x: bool,
+
+    /// Minimum tail length for poly-G/poly-X trimming; default 10
+    #[arg(long, default_value_t = 10)]
+    pub poly_min_len: usize,
+
+    /// Fraction of mismatching bases tolerated inside a poly-G/poly-X tail; default 0.125
+    #[arg(long, default_value_t = 0.125)]
+    pub poly_mismatch_rate: f64,
+
+    /// Trim 3' poly-A tails and 5' poly-T heads (RNA-seq, nanopore cDNA)
+    #[arg(long, default_value_t = false)]
+    pub poly_a: bool,
+
+    /// Minimum poly-A/poly-T length to trim; default 10
+    #[arg(long, default_value_t = 10)]
+    pub poly_a_min_len: usize,
+
+    /// Fraction of mismatching bases tolerated inside a poly-A/poly-T run; default 0.2
+    #[arg(long, default_value_t = 0.2)]
+    pub poly_a_mismatch_rate: f64,
+
+    /// Append the removed poly-A/poly-T lengths to each header as `pA:i:<n> pT:i:<n>`
+    #[arg(long, default_value_t = false, requires = "poly_a")]
+    pub poly_a_tag: bool,
+
+    /// Primer FASTA for amplicon data; names end in `_F`/`_fwd` (R1) or `_R`/`_rev` (R2).
+    /// Primers anchored at the 5' ends are removed and the matched pair is
+    /// added to the header as `pr:Z:<pair>`
+    #[arg(long)]
+    pub primers: Option<String>,
+
+    /// Mismatches allowed when matching a primer (IUPAC codes match their bases); default 2
+    #[arg(long, default_value_t = 2)]
+    pub primer_mismatches: usize,
+
+    /// Write reads (pairs) without a primer here instead of trimming them as usual;
+    /// a file name in single-end mode, a base name like `--output` in paired mode
+    #[arg(long, requires = "primers")]
+    pub no_primer_output: Option<String>,
+
+    /// Paired mode: detect the insert size from the R1/R2 overlap and cut both
+    /// mates at the insert boundary (no adapter sequence needed)
+    #[arg(long, default_value_t = false)]
+    pub detect_overlap: bool,
+
+    /// Minimum R1/R2 overlap length for insert detection; default 30
+    #[arg(long, default_value_t = 30)]
+    pub overlap_min_len: usize,
+
+    /// Maximum mismatches allowed in the R1/R2 overlap; default 5
+    #[arg(long, default_value_t = 5)]
+    pub overlap_diff_limit: usize,
+
+    /// Maximum percentage of mismatches allowed in the R1/R2 overlap; default 20
+    #[arg(long, default_value_t = 20)]
+    pub overlap_diff_percent: u8,
+
+    /// Paired mode: merge overlapping mates (after trimming) into one consensus
+    /// read written to `<output>_merged.fastq(.gz)`; uses the `--overlap-*` settings
+    #[arg(long, default_value_t = false, requires = "p1")]
+    pub merge: bool,
+
     /// Output base name for paired output files (required for paired mode).
     /// For paired mode this will create `<output>_R1.fastq(.gz)`,
-    /// `<output>_R2.fastq(.gz)` and `<output>_singletons.fastq(.gz)`.
+    /// `<output>_R2.fastq(.gz)` and `<output>_singletons.fastq(.gz)`, plus
+    /// `<output>_merged.fastq(.gz)` with `--merge`.
     #[arg(long)]
     pub output: Option<String>,
 
-    /// Use gzip compression for outputs (enabled by default)
-    #[arg(long, default_value_t = true)]
+    /// Use gzip compression for outputs (enabled by default, off with `--zstd`)
+    #[arg(
+        long,
+        default_value_t = true,
+        default_value_if("zstd", ArgPredicate::IsPresent, "false")
+    )]
     pub gz: bool,
 
     /// Gzip compression level for outputs (0-9). Lower is faster; 1 is a sensible fast default.
@@ -64,19 +324,141 @@ pub struct Args {
     /// zstd compression level (1-19). Lower is faster; default 3 is a sensible fast default.
     #[arg(long, default_value_t = 3)]
     pub zstd_level: i32,
+
+    /// Write gzip outputs as BGZF (blocked gzip), which htslib tools such as
+    /// `samtools fqidx` can index. Uses `--gz-level`
+    #[arg(long, conflicts_with = "zstd")]
+    pub bgzf: bool,
+
+    /// With `--bgzf`, also write a `.gzi` block index next to each output
+    #[arg(long, requires = "bgzf")]
+    pub gzi: bool,
+
+    /// Number of worker threads used to trim reads and compress gzip output;
+    /// 0 uses all available cores. Output is identical for any thread count
+    #[arg(long, default_value_t = 1)]
+    pub threads: usize,
 }
 
+/// Number of reads (or pairs) trimmed in parallel before they are written out.
+const BATCH_SIZE: usize = 10_000;
+
 pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
     let start = Instant::now();
 
     if args.gz && args.zstd {
         return Err("Error: --gz and --zstd are mutually exclusive".into());
     }
+    if args.trim_mode == TrimMode::Mott
+        && (args.window != 1 || args.window_front.is_some() || args.window_back.is_some())
+    {
+        return Err(
+            "Error: --window, --window-front and --window-back do not apply to --trim-mode mott"
+                .into(),
+        );
+    }
+    let format = if args.bgzf {
+        OutputFormat::Bgzf {
+            level: args.gz_level,
+            index: args.gzi,
+        }
+    } else if args.gz {
+        OutputFormat::Gzip(args.gz_level)
+    } else if args.zstd {
+        OutputFormat::Zstd(args.zstd_level)
+    } else {
+        OutputFormat::Plain
+    };
+
+    let (adapter_r1, adapter_r2) = if args.adapter_auto {
+        match (
+            args.input.as_deref(),
+            args.p1.as_deref(),
+            args.p2.as_deref(),
+        ) {
+            (Some(path), None, None) => (infer_adapter(path, "", &args)?, None),
+            (None, Some(p1), Some(p2)) => (
+                infer_adapter(p1, " R1", &args)?,
+                infer_adapter(p2, " R2", &args)?,
+            ),
+            _ => (None, None),
+        }
+    } else {
+        adapter::select_adapters(
+            args.adapter.as_ref(),
+            args.adapter2.as_ref(),
+            args.adapter_preset,
+        )
+    };
+
+    // qualities are handled as Phred+33 internally; stdin sampled for
+    // `--phred auto` is read on from `stdin_input`
+    let mut stdin_input = None;
+    let phred_in = match args.phred.offset() {
+        Some(offset) => offset,
+        None => match (args.input.as_deref(), args.p1.as_deref()) {
+            (Some(path), _) | (None, Some(path)) => {
+                let quals = if path == "-" {
+                    let sampled = io_utils::sample_stdin_qualities(quality::DETECT_READS)?;
+                    stdin_input = Some(sampled.reader);
+                    sampled.quals
+                } else {
+                    io_utils::sample_qualities(path, quality::DETECT_READS)?
+                };
+                detect_phred(&quals)?
+            }
+            _ => 33,
+        },
+    };
+    let mut open_reader = |path: &str| match stdin_input.take() {
+        Some(reader) if path == "-" => Ok(reader),
+        _ => open_input(path),
+    };
+    let phred_out = if args.to_phred33 { 33 } else { phred_in };
+    let mut qual_out = QualOutput::new(phred_out, args.qual_bins.clone());
+
+    let crop_r1 = Crop {
+        head: args.head_crop,
+        tail: args.tail_crop,
+        max_len: args.crop_to,
+    };
+    let crop_r2 = Crop {
+        head: args.head_crop2.unwrap_or(args.head_crop),
+        tail: args.tail_crop2.unwrap_or(args.tail_crop),
+        max_len: args.crop_to2.or(args.crop_to),
+    };
+
+    let primers = match &args.primers {
+        Some(path) => Some(PrimerSet::from_fasta(path)?),
+        None => None,
+    };
+    let mut primer_counts: BTreeMap<String, u64> = BTreeMap::new();
+    let mut no_primer: u64 = 0;
+
+    // shared by the trimming batches and the gzip writers
+    let pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(args.threads)
+            .build()?,
+    );
+    let trimmer = Trimmer {
+        args: &args,
+        adapter_r1: adapter_r1.as_ref(),
+        adapter_r2: adapter_r2.as_ref(),
+        crop_r1,
+        crop_r2,
+        primers: primers.as_ref(),
+        phred_in,
+    };
 
-    match (args.input, args.p1, args.p2) {
+    match (
+        args.input.as_deref(),
+        args.p1.as_deref(),
+        args.p2.as_deref(),
+    ) {
         (Some(path), None, None) => {
             // single-end mode: trimming enabled by default (counts kept for logging)
-            let reader = open_input(&path)?;
+            let reader = open_reader(path)?;
             let fq = fastq::Reader::new(BufReader::new(reader));
 
             // require `--output` (no stdout allowed)
@@ -87,42 +469,88 @@ pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
                 }
             };
 
-            let f = File::create(out_name)?;
-            let writer: Box<dyn Write> = if args.gz {
-                Box::new(GzEncoder::new(
-                    BufWriter::new(f),
-                    Compression::new(args.gz_level),
-                ))
-            } else if args.zstd {
-                Box::new(ZstdEncoder::new(BufWriter::new(f), args.zstd_level)?.auto_finish())
-            } else {
-                Box::new(BufWriter::new(f))
+            // one writer per length bin, or just `out_name`
+            let out_names = match &args.length_bins {
+                Some(bins) => bins
+                    .labels()
+                    .iter()
+                    .map(|l| io_utils::insert_suffix(out_name, &format!("_len{}", l)))
+                    .collect(),
+                None => vec![out_name.clone()],
+            };
+            let mut writers = Vec::with_capacity(out_names.len());
+            for name in &out_names {
+                writers.push(io_utils::create_writer(name, format, &pool)?);
+            }
+            let mut bin_counts = vec![0u64; writers.len()];
+            let mut w_noprimer = match &args.no_primer_output {
+                Some(name) => Some(io_utils::create_writer(name, format, &pool)?),
+                None => None,
             };
-            let mut fqw = fastq::Writer::new(writer);
 
             let mut kept: u64 = 0;
             let mut dropped: u64 = 0;
             let mut read_count: u64 = 0;
             let mut base_count: u64 = 0;
+            let mut trim_stats = TrimStats::default();
 
-            for result in fq.records() {
-                let rec = result?;
-                read_count += 1;
-                base_count += rec.seq().len() as u64;
-                if let Some((seq, qual)) =
-                    trim_record(rec.qual(), rec.seq(), args.qual, args.min_len, args.window)
-                {
-                    // write record with same id/desc
-                    fqw.write(rec.id(), rec.desc(), &seq, &qual)?;
-                    kept += 1;
-                } else {
-                    dropped += 1;
+            let mut records = fq.records();
+            loop {
+                let batch = records
+                    .by_ref()
+                    .take(BATCH_SIZE)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if batch.is_empty() {
+                    break;
                 }
+                let outcomes: Vec<Outcome> =
+                    pool.install(|| batch.par_iter().map(|raw| trimmer.single(raw)).collect());
+
+                for (raw, out) in batch.iter().zip(outcomes) {
+                    read_count += 1;
+                    base_count += raw.seq().len() as u64;
+                    trim_stats.merge(&out.stats);
+                    if let Some(pair) = out.primer {
+                        *primer_counts.entry(pair).or_insert(0) += 1;
+                    }
+                    no_primer += out.no_primer as u64;
+                    match out.action {
+                        Action::NoPrimer(read) => {
+                            if let Some(w) = &mut w_noprimer {
+                                write_read(w, raw, &read, &mut qual_out)?;
+                            }
+                        }
+                        Action::Mate1(read) => {
+                            let bin = args
+                                .length_bins
+                                .as_ref()
+                                .map_or(0, |b| b.bin(read.seq.len()));
+                            // write record with same id/desc
+                            write_read(&mut writers[bin], raw, &read, &mut qual_out)?;
+                            bin_counts[bin] += 1;
+                            kept += 1;
+                        }
+                        _ => dropped += 1,
+                    }
+                }
+            }
+            for w in writers.into_iter().chain(w_noprimer) {
+                w.finish()?;
             }
 
             eprintln!("trimmed kept: {}  dropped: {}", kept, dropped);
             println!("reads: {}", read_count);
             println!("bases: {}", base_count);
+            print_trim_stats(&trim_stats, &args, adapter_r1.is_some());
+            if primers.is_some() {
+                print_primer_stats(&primer_counts, no_primer);
+            }
+            print_qual_bin_stats(&qual_out);
+            if let Some(bins) = &args.length_bins {
+                for (label, n) in bins.labels().iter().zip(&bin_counts) {
+                    println!("length {}: {}", label, n);
+                }
+            }
         }
         (None, Some(p1), Some(p2)) => {
             // paired-end mode: require output base name to write R1/R2 and singletons
@@ -133,46 +561,33 @@ pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
                 }
             };
 
-            // build output filenames based on `--gz`/`--zstd` flags
-            let (r1_name, r2_name, single_name) =
-                io_utils::make_output_files(&out_base, args.gz, args.zstd);
-
-            // when using zstd we will write plain fastq files first then compress to .zst
-            let (r1_plain, r2_plain, single_plain) =
-                io_utils::make_plain_filenames(&r1_name, &r2_name, &single_name, args.zstd);
-
-            // open input readers for counting/processing
-            let _ = open_input(&p1)?; // validate paths early
-            let _ = open_input(&p2)?;
+            // build output filenames based on the output format
+            let (r1_name, r2_name, single_name) = io_utils::make_output_files(&out_base, format);
 
-            let r1_proc = open_input(&p1)?;
-            let r2_proc = open_input(&p2)?;
+            let r1_proc = open_reader(p1)?;
+            let r2_proc = open_reader(p2)?;
 
             let fq1 = fastq::Reader::new(BufReader::new(r1_proc));
             let fq2 = fastq::Reader::new(BufReader::new(r2_proc));
 
             // prepare output writers
-            let make_writer = |name: &str| -> Result<Box<dyn Write>, Box<dyn Error>> {
-                let f = File::create(name)?;
-                if args.gz {
-                    Ok(Box::new(GzEncoder::new(
-                        BufWriter::new(f),
-                        Compression::new(args.gz_level),
-                    )))
-                } else {
-                    // For zstd we create plain fastq files (no streaming encoder here)
-                    Ok(Box::new(BufWriter::new(f)))
+            let open = |name: &str| io_utils::create_writer(name, format, &pool);
+            let mut w_r1 = open(&r1_name)?;
+            let mut w_r2 = open(&r2_name)?;
+            let mut w_s = open(&single_name)?;
+            let mut w_merged = if args.merge {
+                Some(open(&io_utils::make_merged_file(&out_base, format))?)
+            } else {
+                None
+            };
+            let mut w_noprimer = match &args.no_primer_output {
+                Some(base) => {
+                    let (n1, n2, _) = io_utils::make_output_files(base, format);
+                    Some((open(&n1)?, open(&n2)?))
                 }
+                None => None,
             };
 
-            let w1 = make_writer(&r1_plain)?;
-            let w2 = make_writer(&r2_plain)?;
-            let ws = make_writer(&single_plain)?;
-
-            let mut w_r1 = fastq::Writer::new(w1);
-            let mut w_r2 = fastq::Writer::new(w2);
-            let mut w_s = fastq::Writer::new(ws);
-
             // iterate records in lock-step, handle leftovers as singletons
             let mut iter1 = fq1.records();
             let mut iter2 = fq2.records();
@@ -183,82 +598,81 @@ pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
             let mut singletons: u64 = 0;
             let mut read_r1: u64 = 0;
             let mut read_r2: u64 = 0;
+            let mut trim_stats = TrimStats::default();
+            let mut insert_sizes = Histogram::default();
+            let mut pairs_merged: u64 = 0;
+            let mut merge_overlaps = Histogram::default();
 
             loop {
-                match (iter1.next(), iter2.next()) {
-                    (None, None) => break,
-                    (Some(r1_res), Some(r2_res)) => {
-                        let rec1 = r1_res?;
-                        let rec2 = r2_res?;
-                        read_r1 += 1;
-                        read_r2 += 1;
-                        pairs_total += 1;
-
-                        let t1 = trim_record(
-                            rec1.qual(),
-                            rec1.seq(),
-                            args.qual,
-                            args.min_len,
-                            args.window,
-                        );
-                        let t2 = trim_record(
-                            rec2.qual(),
-                            rec2.seq(),
-                            args.qual,
-                            args.min_len,
-                            args.window,
-                        );
+                let mut batch = Vec::with_capacity(BATCH_SIZE);
+                while batch.len() < BATCH_SIZE {
+                    match (iter1.next(), iter2.next()) {
+                        (None, None) => break,
+                        (r1_res, r2_res) => batch.push((r1_res.transpose()?, r2_res.transpose()?)),
+                    }
+                }
+                if batch.is_empty() {
+                    break;
+                }
+                let outcomes: Vec<Outcome> = pool.install(|| {
+                    batch
+                        .par_iter()
+                        .map(|(raw1, raw2)| trimmer.paired(raw1.as_ref(), raw2.as_ref()))
+                        .collect()
+                });
 
-                        match (t1, t2) {
-                            (Some((seq1, qual1)), Some((seq2, qual2))) => {
-                                w_r1.write(rec1.id(), rec1.desc(), &seq1, &qual1)?;
-                                w_r2.write(rec2.id(), rec2.desc(), &seq2, &qual2)?;
-                                pairs_kept += 1;
-                            }
-                            (Some((seq1, qual1)), None) => {
-                                w_s.write(rec1.id(), rec1.desc(), &seq1, &qual1)?;
-                                singletons += 1;
-                            }
-                            (None, Some((seq2, qual2))) => {
-                                w_s.write(rec2.id(), rec2.desc(), &seq2, &qual2)?;
-                                singletons += 1;
+                for ((raw1, raw2), out) in batch.iter().zip(outcomes) {
+                    read_r1 += raw1.is_some() as u64;
+                    read_r2 += raw2.is_some() as u64;
+                    let is_pair = raw1.is_some() && raw2.is_some();
+                    pairs_total += is_pair as u64;
+                    trim_stats.merge(&out.stats);
+                    if let Some(pair) = out.primer {
+                        *primer_counts.entry(pair).or_insert(0) += 1;
+                    }
+                    no_primer += out.no_primer as u64;
+                    if let Some(size) = out.insert {
+                        insert_sizes.add(size);
+                    }
+
+                    match (raw1, raw2, out.action) {
+                        (Some(rec1), Some(rec2), Action::NoPrimerPair(read1, read2)) => {
+                            if let Some((wa, wb)) = &mut w_noprimer {
+                                write_read(wa, rec1, &read1, &mut qual_out)?;
+                                write_read(wb, rec2, &read2, &mut qual_out)?;
                             }
-                            (None, None) => {
-                                pairs_dropped += 1;
+                        }
+                        (Some(rec1), Some(rec2), Action::Pair(read1, read2)) => {
+                            write_read(&mut w_r1, rec1, &read1, &mut qual_out)?;
+                            write_read(&mut w_r2, rec2, &read2, &mut qual_out)?;
+                            pairs_kept += 1;
+                        }
+                        (Some(rec1), Some(_), Action::Merged(read, overlap)) => {
+                            if let Some(w) = &mut w_merged {
+                                write_read(w, rec1, &read, &mut qual_out)?;
                             }
+                            merge_overlaps.add(overlap);
+                            pairs_merged += 1;
                         }
-                    }
-                    (Some(r1_res), None) => {
-                        let rec1 = r1_res?;
-                        read_r1 += 1;
-                        // no partner - handle as singleton if it survives trimming
-                        if let Some((seq1, qual1)) = trim_record(
-                            rec1.qual(),
-                            rec1.seq(),
-                            args.qual,
-                            args.min_len,
-                            args.window,
-                        ) {
-                            w_s.write(rec1.id(), rec1.desc(), &seq1, &qual1)?;
+                        (Some(rec1), _, Action::Mate1(read1)) => {
+                            write_read(&mut w_s, rec1, &read1, &mut qual_out)?;
                             singletons += 1;
                         }
-                    }
-                    (None, Some(r2_res)) => {
-                        let rec2 = r2_res?;
-                        read_r2 += 1;
-                        if let Some((seq2, qual2)) = trim_record(
-                            rec2.qual(),
-                            rec2.seq(),
-                            args.qual,
-                            args.min_len,
-                            args.window,
-                        ) {
-                            w_s.write(rec2.id(), rec2.desc(), &seq2, &qual2)?;
+                        (_, Some(rec2), Action::Mate2(read2)) => {
+                            write_read(&mut w_s, rec2, &read2, &mut qual_out)?;
                             singletons += 1;
                         }
+                        _ => pairs_dropped += is_pair as u64,
                     }
                 }
             }
+            for w in [w_r1, w_r2, w_s].into_iter().chain(w_merged) {
+                w.finish()?;
+            }
+            if let Some((wa, wb)) = w_noprimer {
+                wa.finish()?;
+                wb.finish()?;
+            }
 
             println!("R1 reads: {}", read_r1);
             println!("R2 reads: {}", read_r2);
@@ -266,29 +680,36 @@ pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
             println!("pairs kept: {}", pairs_kept);
             println!("pairs dropped: {}", pairs_dropped);
             println!("singletons: {}", singletons);
+            if args.merge {
+                println!("pairs merged: {}", pairs_merged);
+                let rate = if pairs_total > 0 {
+                    100.0 * pairs_merged as f64 / pairs_total as f64
+                } else {
+                    0.0
+                };
+                println!("merge rate: {:.2}%", rate);
+            }
+            print_trim_stats(
+                &trim_stats,
+                &args,
+                adapter_r1.is_some() || adapter_r2.is_some(),
+            );
+            if primers.is_some() {
+                print_primer_stats(&primer_counts, no_primer);
+            }
+            print_qual_bin_stats(&qual_out);
+            if args.detect_overlap {
+                insert_sizes.print("insert size");
+            }
+            if args.merge {
+                merge_overlaps.print("merge overlap");
+            }
             if read_r1 != read_r2 {
                 eprintln!(
                     "warning: R1 and R2 have different read counts ({} != {})",
                     read_r1, read_r2
                 );
             }
-
-            // If zstd requested, compress the plain fastq files to .zst now.
-            if args.zstd {
-                let compress = |plain: &str, out_zst: &str| -> Result<(), Box<dyn Error>> {
-                    let mut src = File::open(plain)?;
-                    let dst = File::create(out_zst)?;
-                    let mut enc = ZstdEncoder::new(dst, args.zstd_level)?;
-                    copy(&mut src, &mut enc)?;
-                    enc.finish()?;
-                    std::fs::remove_file(plain)?;
-                    Ok(())
-                };
-
-                compress(&r1_plain, &r1_name)?;
-                compress(&r2_plain, &r2_name)?;
-                compress(&single_plain, &single_name)?;
-            }
         }
         _ => {
             return Err("Error: provide either a positional input or both --p1 and --p2".into());
@@ -300,3 +721,652 @@ pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Detect the quality encoding from the first reads' qualities for `--phred auto`.
+fn detect_phred(quals: &[Vec<u8>]) -> Result<u8, Box<dyn Error>> {
+    let offset = quality::detect_offset(quals).ok_or(
+        "Error: cannot tell Phred+33 from Phred+64 qualities in the first reads; \
+         pass --phred 33 or --phred 64",
+    )?;
+    if offset != 33 {
+        println!("detected quality encoding: Phred+{}", offset);
+    }
+    Ok(offset)
+}
+
+/// Sample the first reads of `path` and infer its 3' adapter for `--adapter-auto`.
+/// The inferred sequence and its read support are printed so the choice can be audited.
+fn infer_adapter(path: &str, label: &str, args: &Args) -> Result<Option<Adapter>, Box<dyn Error>> {
+    if path == "-" {
+        return Err("Error: --adapter-auto cannot sample reads from stdin".into());
+    }
+    let reads = io_utils::sample_sequences(path, args.adapter_auto_reads)?;
+    match adapter::infer_adapter(&reads, adapter::INFER_K) {
+        Some(found) => {
+            println!(
+                "inferred adapter{}: {} (supported by {} of {} sampled reads)",
+                label,
+                String::from_utf8_lossy(&found.seq),
+                found.support,
+                reads.len()
+            );
+            Ok(Some(Adapter::Back(found.seq)))
+        }
+        None => {
+            println!(
+                "inferred adapter{}: none ({} sampled reads)",
+                label,
+                reads.len()
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Print the counters of the optional trimming stages that were enabled.
+fn print_trim_stats(stats: &TrimStats, args: &Args, adapter: bool) {
+    if adapter {
+        println!("adapter trimmed: {}", stats.adapter_trimmed);
+    }
+    // auto-detected poly-G trimming only shows up when two-colour reads were seen
+    if args.poly_g || stats.poly_g_checked > 0 {
+        println!("poly-G trimmed: {}", stats.poly_g_trimmed);
+    }
+    if args.poly_x {
+        println!("poly-X trimmed: {}", stats.poly_x_trimmed);
+    }
+    if args.poly_a {
+        println!("poly-A trimmed: {}", stats.poly_a_trimmed);
+        println!("poly-A bases removed: {}", stats.poly_a_bases);
+        println!("poly-T trimmed: {}", stats.poly_t_trimmed);
+        println!("poly-T bases removed: {}", stats.poly_t_bases);
+    }
+    if args.trim_n {
+        println!("N ends trimmed: {}", stats.n_trimmed);
+        println!("dropped (all N): {}", stats.n_trim_dropped);
+    }
+    if args.n_split {
+        println!("N split: {}", stats.n_split);
+        println!("dropped (N split too short): {}", stats.n_split_dropped);
+    }
+    if args.max_n.is_some() {
+        println!("dropped (max N): {}", stats.max_n_dropped);
+    }
+    if args.max_n_frac.is_some() {
+        println!("dropped (max N fraction): {}", stats.max_n_frac_dropped);
+    }
+    if args.complexity.is_some() {
+        println!("dropped (low complexity): {}", stats.low_complexity_dropped);
+    }
+    if args.truncate_ee.is_some() {
+        println!("EE truncated: {}", stats.ee_truncated);
+        println!(
+            "dropped (EE truncated too short): {}",
+            stats.ee_truncated_dropped
+        );
+    }
+    if args.max_ee.is_some() {
+        println!("dropped (max EE): {}", stats.max_ee_dropped);
+    }
+    if args.max_ee_rate.is_some() {
+        println!("dropped (max EE rate): {}", stats.max_ee_rate_dropped);
+    }
+    if args.max_len.is_some() {
+        println!("dropped (max length): {}", stats.max_len_dropped);
+    }
+    if args.min_mean_qual.is_some() {
+        println!("dropped (mean quality): {}", stats.mean_qual_dropped);
+    }
+    if args.max_unqualified_percent.is_some() {
+        println!("dropped (unqualified bases): {}", stats.unqualified_dropped);
+    }
+    if args.mask_qual.is_some() {
+        println!("masked reads: {}", stats.masked_reads);
+        println!("masked bases: {}", stats.masked_bases);
+    }
+}
+
+/// Print the quality-string size reduction of `--qual-bins`, measured on a sample.
+fn print_qual_bin_stats(qual_out: &QualOutput) {
+    if let Some(sizes) = qual_out.binning_sizes() {
+        println!(
+            "quality bytes sampled: {} (gzip: {} before binning, {} after)",
+            sizes.sampled, sizes.before, sizes.after
+        );
+        let reduction = if sizes.before > 0 {
+            100.0 * (1.0 - sizes.after as f64 / sizes.before as f64)
+        } else {
+            0.0
+        };
+        println!("quality size reduction (gzip, sampled): {:.1}%", reduction);
+    }
+}
+
+/// Print how many reads (pairs) matched each primer pair.
+fn print_primer_stats(counts: &BTreeMap<String, u64>, no_primer: u64) {
+    for (pair, n) in counts {
+        println!("primer {}: {}", pair, n);
+    }
+    println!("no primer: {}", no_primer);
+}
+
+/// True when `--complexity` is set and `read` fails it.
+fn is_low_complexity(read: &TrimmedRead, args: &Args) -> bool {
+    args.complexity.is_some_and(|method| {
+        let threshold = args
+            .complexity_threshold
+            .unwrap_or(method.default_threshold());
+        method.is_low(&read.seq, threshold)
+    })
+}
+
+/// Complexity filter for unpaired reads; counts the read when it is dropped.
+fn drop_low_complexity(read: &TrimmedRead, args: &Args, stats: &mut TrimStats) -> bool {
+    let low = is_low_complexity(read, args);
+    if low {
+        stats.low_complexity_dropped += 1;
+    }
+    low
+}
+
+/// Complexity filter for a pair of trimmed mates. With `--complexity-pair either`
+/// a failing mate takes its partner with it; with `both` the pair is dropped only
+/// when both mates fail. A mate whose partner was already trimmed away is judged
+/// on its own.
+fn filter_pair_complexity(
+    t1: Option<TrimmedRead>,
+    t2: Option<TrimmedRead>,
+    args: &Args,
+    stats: &mut TrimStats,
+) -> (Option<TrimmedRead>, Option<TrimmedRead>) {
+    if args.complexity.is_none() {
+        return (t1, t2);
+    }
+    let low1 = t1.as_ref().is_some_and(|r| is_low_complexity(r, args));
+    let low2 = t2.as_ref().is_some_and(|r| is_low_complexity(r, args));
+    let drop = match (&t1, &t2, args.complexity_pair) {
+        (Some(_), Some(_), PairFilter::Both) => low1 && low2,
+        _ => low1 || low2,
+    };
+    if !drop {
+        return (t1, t2);
+    }
+    stats.low_complexity_dropped += t1.is_some() as u64 + t2.is_some() as u64;
+    (None, None)
+}
+
+/// Shared settings for trimming reads on the worker threads.
+struct Trimmer<'a> {
+    args: &'a Args,
+    adapter_r1: Option<&'a Adapter>,
+    adapter_r2: Option<&'a Adapter>,
+    crop_r1: Crop,
+    crop_r2: Crop,
+    primers: Option<&'a PrimerSet>,
+    phred_in: u8,
+}
+
+/// Where a trimmed read (pair) goes; applied to the outputs in input order.
+#[derive(Default)]
+enum Action {
+    /// Dropped by trimming or a filter
+    #[default]
+    Dropped,
+    /// Written untrimmed to `--no-primer-output`, in the output quality encoding
+    NoPrimer(TrimmedRead),
+    NoPrimerPair(TrimmedRead, TrimmedRead),
+    /// Single-end read, or the only surviving mate of a pair (to singletons)
+    Mate1(TrimmedRead),
+    Mate2(TrimmedRead),
+    Pair(TrimmedRead, TrimmedRead),
+    /// Merged pair and its overlap length
+    Merged(TrimmedRead, usize),
+}
+
+/// Result of trimming one read (pair) on a worker thread, with its own stats.
+#[derive(Default)]
+struct Outcome {
+    action: Action,
+    stats: TrimStats,
+    /// primer pair found at the 5' end(s)
+    primer: Option<String>,
+    /// `--primers` is set but no primer was found
+    no_primer: bool,
+    /// insert size found by `--detect-overlap`
+    insert: Option<usize>,
+}
+
+impl Trimmer<'_> {
+    /// Trim a single-end read.
+    fn single(&self, raw: &fastq::Record) -> Outcome {
+        let args = self.args;
+        let rec = quality::to_phred33(raw, self.phred_in);
+        let mut out = Outcome::default();
+
+        let mut head = 0usize;
+        let mut primer_tag = None;
+        if let Some(set) = self.primers {
+            match set.match_single(rec.seq(), args.primer_mismatches) {
+                Some(p) => {
+                    head = p.seq.len();
+                    out.primer = Some(p.pair.clone());
+                    primer_tag = Some(format!("pr:Z:{}", p.pair));
+                }
+                None => {
+                    out.no_primer = true;
+                    if args.no_primer_output.is_some() {
+                        out.action = Action::NoPrimer(TrimmedRead::untrimmed(&rec));
+                        return out;
+                    }
+                }
+            }
+        }
+
+        let stats = &mut out.stats;
+        if let Some(mut read) =
+            trim_read(&rec, head, None, self.crop_r1, self.adapter_r1, args, stats)
+                .filter(|r| !drop_low_complexity(r, args, stats))
+        {
+            read.tags.extend(primer_tag);
+            out.action = Action::Mate1(read);
+        }
+        out
+    }
+
+    /// Trim a read pair; a missing mate (unequal input lengths) leaves the other
+    /// one to be trimmed as a singleton.
+    fn paired(&self, raw1: Option<&fastq::Record>, raw2: Option<&fastq::Record>) -> Outcome {
+        let (raw1, raw2) = match (raw1, raw2) {
+            (Some(r1), Some(r2)) => (r1, r2),
+            (Some(r1), None) => return self.orphan(r1, false),
+            (None, Some(r2)) => return self.orphan(r2, true),
+            (None, None) => return Outcome::default(),
+        };
+        let args = self.args;
+        let rec1 = quality::to_phred33(raw1, self.phred_in);
+        let rec2 = quality::to_phred33(raw2, self.phred_in);
+        let mut out = Outcome::default();
+
+        let (mut head1, mut head2) = (0usize, 0usize);
+        let mut primer_tag = None;
+        if let Some(set) = self.primers {
+            match set.match_pair(rec1.seq(), rec2.seq(), args.primer_mismatches) {
+                Some(m) => {
+                    head1 = m.len1;
+                    head2 = m.len2;
+                    out.primer = Some(m.pair.to_string());
+                    primer_tag = Some(format!("pr:Z:{}", m.pair));
+                }
+                None => {
+                    out.no_primer = true;
+                    if args.no_primer_output.is_some() {
+                        out.action = Action::NoPrimerPair(
+                            TrimmedRead::untrimmed(&rec1),
+                            TrimmedRead::untrimmed(&rec2),
+                        );
+                        return out;
+                    }
+                }
+            }
+        }
+
+        if args.detect_overlap {
+            out.insert = overlap::detect_insert(
+                rec1.seq(),
+                rec2.seq(),
+                args.overlap_min_len,
+                args.overlap_diff_limit,
+                args.overlap_diff_percent as f64 / 100.0,
+            );
+        }
+
+        let stats = &mut out.stats;
+        let t1 = trim_read(
+            &rec1,
+            head1,
+            out.insert,
+            self.crop_r1,
+            self.adapter_r1,
+            args,
+            stats,
+        );
+        let t2 = trim_read(
+            &rec2,
+            head2,
+            out.insert,
+            self.crop_r2,
+            self.adapter_r2,
+            args,
+            stats,
+        );
+        let (mut t1, mut t2) = filter_pair_complexity(t1, t2, args, stats);
+        if let Some(tag) = primer_tag {
+            for read in [&mut t1, &mut t2].into_iter().flatten() {
+                read.tags.push(tag.clone());
+            }
+        }
+
+        out.action = match (t1, t2) {
+            (Some(read1), Some(read2)) => {
+                let merged = if args.merge {
+                    overlap::merge_pair(
+                        &read1.seq,
+                        &read1.qual,
+                        &read2.seq,
+                        &read2.qual,
+                        args.overlap_min_len,
+                        args.overlap_diff_limit,
+                        args.overlap_diff_percent as f64 / 100.0,
+                    )
+                } else {
+                    None
+                };
+                match merged {
+                    Some(m) => Action::Merged(
+                        TrimmedRead {
+                            seq: m.seq,
+                            qual: m.qual,
+                            tags: read1.tags,
+                        },
+                        m.overlap,
+                    ),
+                    None => Action::Pair(read1, read2),
+                }
+            }
+            (Some(read1), None) => Action::Mate1(read1),
+            (None, Some(read2)) => Action::Mate2(read2),
+            (None, None) => Action::Dropped,
+        };
+        out
+    }
+
+    /// Trim a mate without a partner as a singleton.
+    fn orphan(&self, raw: &fastq::Record, r2: bool) -> Outcome {
+        let args = self.args;
+        let rec = quality::to_phred33(raw, self.phred_in);
+        let mut out = Outcome::default();
+        let (crop, adapter) = if r2 {
+            (self.crop_r2, self.adapter_r2)
+        } else {
+            (self.crop_r1, self.adapter_r1)
+        };
+        let stats = &mut out.stats;
+        if let Some(read) = trim_read(&rec, 0, None, crop, adapter, args, stats)
+            .filter(|r| !drop_low_complexity(r, args, stats))
+        {
+            out.action = if r2 {
+                Action::Mate2(read)
+            } else {
+                Action::Mate1(read)
+            };
+        }
+        out
+    }
+}
+
+/// A read that survived trimming, with optional header tags to append.
+struct TrimmedRead {
+    seq: Vec<u8>,
+    qual: Vec<u8>,
+    tags: Vec<String>,
+}
+
+impl TrimmedRead {
+    /// The whole of `rec` (qualities already Phred+33).
+    fn untrimmed(rec: &fastq::Record) -> Self {
+        TrimmedRead {
+            seq: rec.seq().to_vec(),
+            qual: rec.qual().to_vec(),
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// Write a trimmed read under the original id/description, followed by its tags,
+/// with its qualities binned and encoded by `qual_out`.
+fn write_read(
+    w: &mut io_utils::FastqWriter,
+    rec: &fastq::Record,
+    read: &TrimmedRead,
+    qual_out: &mut QualOutput,
+) -> std::io::Result<()> {
+    let qual = qual_out.apply(&read.qual);
+    if read.tags.is_empty() {
+        return w.write(rec.id(), rec.desc(), &read.seq, &qual);
+    }
+    let tags = read.tags.join(" ");
+    let desc = match rec.desc() {
+        Some(d) => format!("{} {}", d, tags),
+        None => tags,
+    };
+    w.write(rec.id(), Some(&desc), &read.seq, &qual)
+}
+
+/// Shortest read kept after a cut inside the read (`--n-split`, `--truncate-ee`);
+/// `--steps` applies its own MINLEN, so only empty reads are dropped there.
+fn min_kept_len(args: &Args) -> usize {
+    if args.steps.is_some() {
+        1
+    } else {
+        args.min_len.max(1)
+    }
+}
+
+/// Apply the fixed-position `crop`, cut the read after its 5' primer (`head`
+/// bases) and at the detected insert size (paired overlap), then remove its poly-G tail,
+/// the 3' adapter (when one is configured), any poly-X tail, poly-A/poly-T
+/// runs and N's, then quality-trim what is left (`--trim-mode mott` quality-trims
+/// before the poly-G and adapter stages instead, as cutadapt does), apply the read
+/// filters and mask low-quality bases. `stats` counts the reads touched or dropped by each stage.
+fn trim_read(
+    rec: &fastq::Record,
+    head: usize,
+    insert: Option<usize>,
+    crop: Crop,
+    adapter: Option<&Adapter>,
+    args: &Args,
+    stats: &mut TrimStats,
+) -> Option<TrimmedRead> {
+    // fixed-position crops count from the ends of the read as sequenced
+    let (crop_start, mut end) = crop.range(rec.seq().len().min(rec.qual().len()));
+    if let Some(size) = insert {
+        end = end.min(size);
+    }
+    let mut start = head.max(crop_start).min(end);
+    // like `cutadapt -q`, Mott trimming runs before adapter removal and without
+    // the automatic two-colour poly-G trimming
+    let mott = args.steps.is_none() && args.trim_mode == TrimMode::Mott;
+    if mott {
+        let (s, e) = trim::mott_trim_index(
+            &rec.qual()[start..end],
+            args.qual_front.unwrap_or(0),
+            args.qual_back.unwrap_or(args.qual),
+        );
+        end = start + e;
+        start += s;
+    }
+    let poly_g =
+        args.poly_g || (!mott && !args.no_poly_g && polyx::is_two_colour(rec.id(), rec.desc()));
+    if poly_g {
+        stats.poly_g_checked += 1;
+        let tail = polyx::tail_len(
+            &rec.seq()[start..end],
+            b'G',
+            args.poly_min_len,
+            args.poly_mismatch_rate,
+        );
+        if tail > 0 {
+            end -= tail;
+            stats.poly_g_trimmed += 1;
+        }
+    }
+    if let Some(a) = adapter {
+        if let Some((s, e)) = a.find(
+            &rec.seq()[start..end],
+            args.adapter_error_rate,
+            args.adapter_min_overlap,
+        ) {
+            end = start + e;
+            start += s;
+            stats.adapter_trimmed += 1;
+        }
+    }
+    if args.poly_x {
+        if let Some((_, tail)) = polyx::longest_tail(
+            &rec.seq()[start..end],
+            args.poly_min_len,
+            args.poly_mismatch_rate,
+        ) {
+            end -= tail;
+            stats.poly_x_trimmed += 1;
+        }
+    }
+    let mut tags = Vec::new();
+    if args.poly_a {
+        let (poly_t, poly_a) = trim::trim_poly_a(
+            &rec.seq()[start..end],
+            args.poly_a_min_len,
+            args.poly_a_mismatch_rate,
+        );
+        start += poly_t;
+        end -= poly_a;
+        if poly_a > 0 {
+            stats.poly_a_trimmed += 1;
+            stats.poly_a_bases += poly_a as u64;
+        }
+        if poly_t > 0 {
+            stats.poly_t_trimmed += 1;
+            stats.poly_t_bases += poly_t as u64;
+        }
+        if args.poly_a_tag {
+            tags.push(format!("pA:i:{}", poly_a));
+            tags.push(format!("pT:i:{}", poly_t));
+        }
+    }
+    // reads already emptied by the steps above are left to the quality trimming,
+    // which drops them, rather than counted as all-N
+    if args.trim_n && start < end {
+        let (s, e) = trim::trim_n_ends(&rec.seq()[start..end]);
+        if s > 0 || e < end - start {
+            stats.n_trimmed += 1;
+        }
+        if s >= e {
+            stats.n_trim_dropped += 1;
+            return None;
+        }
+        end = start + e;
+        start += s;
+    }
+    if args.n_split {
+        let (s, e) = trim::longest_n_free(&rec.seq()[start..end]);
+        if e - s < end - start {
+            stats.n_split += 1;
+            if e - s < min_kept_len(args) {
+                stats.n_split_dropped += 1;
+                return None;
+            }
+        }
+        end = start + e;
+        start += s;
+    }
+    let (mut seq, mut qual) = if let Some(pipeline) = &args.steps {
+        let (s, e) = pipeline.apply(&rec.qual()[start..end])?;
+        (
+            rec.seq()[start + s..start + e].to_vec(),
+            rec.qual()[start + s..start + e].to_vec(),
+        )
+    } else {
+        match args.trim_mode {
+            TrimMode::Edge => trim_record_ends(
+                &rec.qual()[start..end],
+                &rec.seq()[start..end],
+                (
+                    args.qual_front.unwrap_or(args.qual),
+                    args.window_front.unwrap_or(args.window),
+                ),
+                (
+                    args.qual_back.unwrap_or(args.qual),
+                    args.window_back.unwrap_or(args.window),
+                ),
+                args.min_len,
+            ),
+            // already quality-trimmed above; only the length check is left
+            TrimMode::Mott => trim_record_mott(
+                &rec.qual()[start..end],
+                &rec.seq()[start..end],
+                0,
+                0,
+                args.min_len,
+            ),
+        }?
+    };
+
+    // expected-error truncation and filters run on the trimmed read
+    if let Some(limit) = args.truncate_ee {
+        let keep = filter::truncate_ee(&qual, limit);
+        if keep < qual.len() {
+            seq.truncate(keep);
+            qual.truncate(keep);
+            stats.ee_truncated += 1;
+            if keep < min_kept_len(args) {
+                stats.ee_truncated_dropped += 1;
+                return None;
+            }
+        }
+    }
+    // on the final length, after any EE truncation
+    if args.max_len.is_some_and(|max| seq.len() > max) {
+        stats.max_len_dropped += 1;
+        return None;
+    }
+    if args.max_n.is_some() || args.max_n_frac.is_some() {
+        let n = filter::count_n(&seq);
+        if args.max_n.is_some_and(|max| n > max) {
+            stats.max_n_dropped += 1;
+            return None;
+        }
+        if args
+            .max_n_frac
+            .is_some_and(|max| n as f64 / seq.len() as f64 > max)
+        {
+            stats.max_n_frac_dropped += 1;
+            return None;
+        }
+    }
+    if args.max_ee.is_some() || args.max_ee_rate.is_some() {
+        let ee = filter::expected_errors(&qual);
+        if args.max_ee.is_some_and(|max| ee > max) {
+            stats.max_ee_dropped += 1;
+            return None;
+        }
+        if args
+            .max_ee_rate
+            .is_some_and(|max| ee / qual.len() as f64 > max)
+        {
+            stats.max_ee_rate_dropped += 1;
+            return None;
+        }
+    }
+    if args
+        .min_mean_qual
+        .is_some_and(|min| filter::mean_quality(&qual) < min)
+    {
+        stats.mean_qual_dropped += 1;
+        return None;
+    }
+    if args
+        .max_unqualified_percent
+        .is_some_and(|max| filter::unqualified_percent(&qual, args.unqualified_qual) > max)
+    {
+        stats.unqualified_dropped += 1;
+        return None;
+    }
+    if let Some(thr) = args.mask_qual {
+        let masked = trim::mask_low_quality(&mut seq, &qual, thr);
+        if masked > 0 {
+            stats.masked_reads += 1;
+            stats.masked_bases += masked as u64;
+        }
+    }
+    Some(TrimmedRead { seq, qual, tags })
+}
@@ -0,0 +1,196 @@
+use bio::alphabets::dna::revcomp;
+
+/// Detect the insert size of a read pair from the overlap of R1 with the
+/// reverse complement of R2 (fastp-style, no adapter sequence needed).
+///
+/// Offsets where R1 starts before R2's reverse complement (insert at least as
+/// long as R2) are tried first, then offsets where R2's reverse complement starts
+/// before R1, which is the adapter read-through case for inserts shorter than
+/// the reads. An overlap is accepted when it is at least `min_overlap` long and
+/// has no more than `min(max_diff, floor(len * max_diff_frac))` mismatches.
+/// Returns the insert size of the first accepted overlap.
+pub fn detect_insert(
+    r1: &[u8],
+    r2: &[u8],
+    min_overlap: usize,
+    max_diff: usize,
+    max_diff_frac: f64,
+) -> Option<usize> {
+    let r2rc = revcomp(r2);
+    let (len1, len2) = (r1.len(), r2rc.len());
+    let min_overlap = min_overlap.max(1);
+
+    let accepts = |a: &[u8], b: &[u8]| -> bool {
+        let limit = max_diff.min((a.len() as f64 * max_diff_frac).floor() as usize);
+        let mut diff = 0usize;
+        for (x, y) in a.iter().zip(b) {
+            if !x.eq_ignore_ascii_case(y) {
+                diff += 1;
+                if diff > limit {
+                    return false;
+                }
+            }
+        }
+        true
+    };
+
+    // R1 starts `offset` bases before R2's reverse complement
+    let mut offset = 0usize;
+    while offset < len1 {
+        let ov = (len1 - offset).min(len2);
+        if ov < min_overlap {
+            break;
+        }
+        if accepts(&r1[offset..offset + ov], &r2rc[..ov]) {
+            return Some(offset + len2);
+        }
+        offset += 1;
+    }
+
+    // R2's reverse complement starts `shift` bases before R1 (read-through)
+    let mut shift = 1usize;
+    while shift < len2 {
+        let ov = (len2 - shift).min(len1);
+        if ov < min_overlap {
+            break;
+        }
+        if accepts(&r1[..ov], &r2rc[shift..shift + ov]) {
+            return Some(len2 - shift);
+        }
+        shift += 1;
+    }
+
+    None
+}
+
+/// Consensus read of a merged pair (Phred+33 qualities) and the length of the
+/// overlap between the mates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merged {
+    pub seq: Vec<u8>,
+    pub qual: Vec<u8>,
+    pub overlap: usize,
+}
+
+/// Highest Phred score given to a merged base.
+const MERGE_MAX_QUAL: u8 = 41;
+
+/// Merge an overlapping read pair into one read spanning the insert found by
+/// `detect_insert`. Where both mates cover a position and agree, the quality
+/// is the sum of both scores (capped at Q41); where they disagree the base with
+/// the higher quality wins with the difference of the scores (at least Q2).
+pub fn merge_pair(
+    seq1: &[u8],
+    qual1: &[u8],
+    seq2: &[u8],
+    qual2: &[u8],
+    min_overlap: usize,
+    max_diff: usize,
+    max_diff_frac: f64,
+) -> Option<Merged> {
+    let insert = detect_insert(seq1, seq2, min_overlap, max_diff, max_diff_frac)?;
+    let seq2rc = revcomp(seq2);
+    let qual2rc: Vec<u8> = qual2.iter().rev().copied().collect();
+    // position in the insert where R2's reverse complement starts (may be negative
+    // when R2 reads past the start of R1)
+    let start2 = insert as isize - seq2.len() as isize;
+
+    let mut seq = Vec::with_capacity(insert);
+    let mut qual = Vec::with_capacity(insert);
+    let mut overlap = 0usize;
+    for pos in 0..insert {
+        let i2 = pos as isize - start2;
+        let b1 = (pos < seq1.len()).then(|| (seq1[pos], qual1[pos]));
+        let b2 = (i2 >= 0 && (i2 as usize) < seq2rc.len())
+            .then(|| (seq2rc[i2 as usize], qual2rc[i2 as usize]));
+        let (b, q) = match (b1, b2) {
+            (Some((x, qx)), Some((y, qy))) => {
+                overlap += 1;
+                let (sx, sy) = (qx.saturating_sub(33), qy.saturating_sub(33));
+                if x.eq_ignore_ascii_case(&y) {
+                    (x, (sx + sy).min(MERGE_MAX_QUAL) + 33)
+                } else if sx >= sy {
+                    (x, (sx - sy).max(2) + 33)
+                } else {
+                    (y, (sy - sx).max(2) + 33)
+                }
+            }
+            (Some(b), None) | (None, Some(b)) => b,
+            (None, None) => return None,
+        };
+        seq.push(b);
+        qual.push(q);
+    }
+    Some(Merged { seq, qual, overlap })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_insert, merge_pair};
+    use bio::alphabets::dna::revcomp;
+
+    const INSERT: &[u8] = b"ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATGACTGAC";
+
+    #[test]
+    fn short_insert_reads_into_adapters() {
+        // both mates sequence through the whole insert and into their adapters
+        let mut r1 = INSERT.to_vec();
+        r1.extend_from_slice(b"AGATCGGAAGAGCACACGTC");
+        let mut r2 = revcomp(INSERT);
+        r2.extend_from_slice(b"AGATCGGAAGAGCGTCGTGT");
+        assert_eq!(detect_insert(&r1, &r2, 20, 5, 0.2), Some(INSERT.len()));
+    }
+
+    #[test]
+    fn long_insert_partial_overlap() {
+        // R1 covers the first 36 bases, R2 the last 36 -> 26 bp overlap
+        let r1 = &INSERT[..36];
+        let r2 = revcomp(&INSERT[10..]);
+        assert_eq!(detect_insert(r1, &r2, 20, 5, 0.2), Some(INSERT.len()));
+        // requiring more overlap than there is finds nothing
+        assert_eq!(detect_insert(r1, &r2, 30, 5, 0.2), None);
+    }
+
+    #[test]
+    fn tolerates_sequencing_errors_in_overlap() {
+        let mut r1 = INSERT.to_vec();
+        r1[5] = b'A';
+        r1[20] = b'C';
+        let r2 = revcomp(INSERT);
+        assert_eq!(detect_insert(&r1, &r2, 20, 5, 0.2), Some(INSERT.len()));
+        assert_eq!(detect_insert(&r1, &r2, 20, 1, 0.2), None);
+    }
+
+    #[test]
+    fn merges_partial_overlap_with_consensus() {
+        // R1 covers the first 36 bases, R2 the last 36; one error in R1 at a Q10 base
+        let mut r1 = INSERT[..36].to_vec();
+        r1[30] = b'A';
+        let mut q1 = vec![b'5'; 36];
+        q1[30] = b'+';
+        let r2 = revcomp(&INSERT[10..]);
+        let q2 = vec![b'5'; r2.len()];
+
+        let m = merge_pair(&r1, &q1, &r2, &q2, 20, 5, 0.2).unwrap();
+        assert_eq!(m.seq, INSERT.to_vec());
+        assert_eq!(m.overlap, 26);
+        // Q20 only in R1, Q20+Q20 in the overlap, Q20-Q10 where R2 wins
+        assert_eq!(m.qual[0], b'5');
+        assert_eq!(m.qual[20], b'I');
+        assert_eq!(m.qual[30], b'+');
+        assert_eq!(m.qual[45], b'5');
+    }
+
+    #[test]
+    fn merged_read_drops_adapter_read_through() {
+        let mut r1 = INSERT.to_vec();
+        r1.extend_from_slice(b"AGATCGGAAGAGCACACGTC");
+        let mut r2 = revcomp(INSERT);
+        r2.extend_from_slice(b"AGATCGGAAGAGCGTCGTGT");
+        let q = vec![b'I'; r1.len()];
+        let m = merge_pair(&r1, &q, &r2, &q, 20, 5, 0.2).unwrap();
+        assert_eq!(m.seq, INSERT.to_vec());
+        assert_eq!(m.overlap, INSERT.len());
+        assert!(merge_pair(&r1[..30], &q[..30], &r2[..30], &q[..30], 20, 5, 0.2).is_none());
+    }
+}
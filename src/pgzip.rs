@@ -0,0 +1,403 @@
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::{Compression, Crc};
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Uncompressed size of each gzip member.
+pub const BLOCK_SIZE: usize = 1 << 20;
+
+/// Uncompressed size of each BGZF block (as in htslib), small enough for the
+/// compressed block to fit in 64 KiB.
+pub const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// Largest compressed BGZF block, header and trailer included.
+const BGZF_MAX_BLOCK: usize = 1 << 16;
+
+/// BGZF header (18 bytes) and gzip trailer (8 bytes) around the deflate data.
+const BGZF_OVERHEAD: usize = 26;
+
+/// The empty block that marks the end of a BGZF file.
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// BGZF state: block offsets for the optional `.gzi` index.
+struct Bgzf {
+    index: Option<Box<dyn Write>>,
+    entries: Vec<(u64, u64)>,
+    compressed: u64,
+    uncompressed: u64,
+}
+
+/// Gzip writer that compresses fixed-size blocks on a thread pool (like pigz)
+/// and writes each block as its own gzip member, in input order.
+///
+/// The result is a valid multi-member gzip stream (readable by
+/// `MultiGzDecoder` and `gzip -d`), and the bytes written do not depend on the
+/// number of threads in the pool. With `bgzf` the members are BGZF blocks,
+/// which htslib can index and seek into.
+pub struct ParGzWriter<W: Write> {
+    inner: Option<W>,
+    level: Compression,
+    pool: Arc<ThreadPool>,
+    block_size: usize,
+    bgzf: Option<Bgzf>,
+    buf: Vec<u8>,
+    blocks: Vec<Vec<u8>>,
+    members: u64,
+}
+
+impl<W: Write> ParGzWriter<W> {
+    pub fn new(inner: W, level: Compression, pool: Arc<ThreadPool>) -> Self {
+        ParGzWriter {
+            inner: Some(inner),
+            level,
+            pool,
+            block_size: BLOCK_SIZE,
+            bgzf: None,
+            buf: Vec::with_capacity(BLOCK_SIZE),
+            blocks: Vec::new(),
+            members: 0,
+        }
+    }
+
+    /// BGZF writer; when `index` is given, a `.gzi` index of the block
+    /// offsets (as written by `bgzip -i`) is written to it on finish.
+    pub fn bgzf(
+        inner: W,
+        level: Compression,
+        pool: Arc<ThreadPool>,
+        index: Option<Box<dyn Write>>,
+    ) -> Self {
+        ParGzWriter {
+            inner: Some(inner),
+            level,
+            pool,
+            block_size: BGZF_BLOCK_SIZE,
+            bgzf: Some(Bgzf {
+                index,
+                entries: Vec::new(),
+                compressed: 0,
+                uncompressed: 0,
+            }),
+            buf: Vec::with_capacity(BGZF_BLOCK_SIZE),
+            blocks: Vec::new(),
+            members: 0,
+        }
+    }
+
+    /// Compress the queued blocks in parallel and write them out in order.
+    fn write_blocks(&mut self) -> io::Result<()> {
+        if self.blocks.is_empty() {
+            return Ok(());
+        }
+        let level = self.level;
+        let bgzf = self.bgzf.is_some();
+        let blocks = std::mem::take(&mut self.blocks);
+        let members: Vec<io::Result<Vec<u8>>> = self.pool.install(|| {
+            blocks
+                .par_iter()
+                .map(|block| {
+                    if bgzf {
+                        return bgzf_block(block, level);
+                    }
+                    let mut enc = GzEncoder::new(Vec::with_capacity(block.len() / 2), level);
+                    enc.write_all(block)?;
+                    enc.finish()
+                })
+                .collect()
+        });
+        let inner = self.inner.as_mut().expect("writer used after finish");
+        for (member, block) in members.into_iter().zip(&blocks) {
+            let member = member?;
+            if let Some(b) = &mut self.bgzf {
+                // like htslib, the index lists every block but the first
+                if b.compressed > 0 {
+                    b.entries.push((b.compressed, b.uncompressed));
+                }
+                b.compressed += member.len() as u64;
+                b.uncompressed += block.len() as u64;
+            }
+            inner.write_all(&member)?;
+            self.members += 1;
+        }
+        Ok(())
+    }
+
+    /// Queue the buffered data as a block, compressing once every thread has one.
+    fn queue_block(&mut self) -> io::Result<()> {
+        let block = std::mem::replace(&mut self.buf, Vec::with_capacity(self.block_size));
+        self.blocks.push(block);
+        if self.blocks.len() >= self.pool.current_num_threads() {
+            self.write_blocks()?;
+        }
+        Ok(())
+    }
+
+    /// Write the remaining data and return the inner writer. An empty input
+    /// still gives one (empty) gzip member, as `GzEncoder` does; BGZF output
+    /// ends with the EOF marker block. The writer is finished even when this
+    /// fails, so dropping it does not write the EOF block or index again.
+    pub fn finish(mut self) -> io::Result<W> {
+        let res = self.try_finish();
+        let inner = self.inner.take().expect("writer used after finish");
+        res.map(|()| inner)
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        let empty = self.members == 0 && self.blocks.is_empty();
+        if !self.buf.is_empty() || empty && self.bgzf.is_none() {
+            self.queue_block()?;
+        }
+        self.write_blocks()?;
+        let inner = self.inner.as_mut().expect("writer used after finish");
+        if let Some(b) = &mut self.bgzf {
+            inner.write_all(&BGZF_EOF)?;
+            if let Some(mut index) = b.index.take() {
+                index.write_all(&(b.entries.len() as u64).to_le_bytes())?;
+                for (compressed, uncompressed) in &b.entries {
+                    index.write_all(&compressed.to_le_bytes())?;
+                    index.write_all(&uncompressed.to_le_bytes())?;
+                }
+                index.flush()?;
+            }
+        }
+        inner.flush()
+    }
+}
+
+/// One BGZF block: a gzip member whose `BC` extra field holds its size.
+/// Data that does not shrink enough is stored uncompressed to fit in 64 KiB.
+fn bgzf_block(data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+    let deflate = |level: Compression| -> io::Result<Vec<u8>> {
+        let mut enc = DeflateEncoder::new(Vec::with_capacity(data.len() / 2), level);
+        enc.write_all(data)?;
+        enc.finish()
+    };
+    let mut deflated = deflate(level)?;
+    if deflated.len() + BGZF_OVERHEAD > BGZF_MAX_BLOCK {
+        deflated = deflate(Compression::none())?;
+    }
+    let mut crc = Crc::new();
+    crc.update(data);
+    let bsize = (deflated.len() + BGZF_OVERHEAD - 1) as u16;
+
+    let mut block = Vec::with_capacity(deflated.len() + BGZF_OVERHEAD);
+    block.extend_from_slice(&BGZF_EOF[..16]);
+    block.extend_from_slice(&bsize.to_le_bytes());
+    block.extend_from_slice(&deflated);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(block)
+}
+
+impl<W: Write> Write for ParGzWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        if self.buf.len() == self.block_size {
+            self.queue_block()?;
+        }
+        Ok(n)
+    }
+
+    /// Compress everything written so far, ending the current member early.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.queue_block()?;
+        }
+        self.write_blocks()?;
+        self.inner
+            .as_mut()
+            .expect("writer used after finish")
+            .flush()
+    }
+}
+
+impl<W: Write> Drop for ParGzWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParGzWriter, BGZF_BLOCK_SIZE, BGZF_EOF, BLOCK_SIZE};
+    use flate2::read::MultiGzDecoder;
+    use flate2::Compression;
+    use std::cell::RefCell;
+    use std::io::{self, Read, Write};
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    fn pool(threads: usize) -> Arc<rayon::ThreadPool> {
+        Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn compress(data: &[u8], threads: usize) -> Vec<u8> {
+        let mut w = ParGzWriter::new(Vec::new(), Compression::fast(), pool(threads));
+        for chunk in data.chunks(1000) {
+            w.write_all(chunk).unwrap();
+        }
+        w.finish().unwrap()
+    }
+
+    fn decompress(gz: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        MultiGzDecoder::new(gz).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    fn fastq(len: usize) -> Vec<u8> {
+        b"@r1\nACGTTGCA\n+\nIIIIFFFF\n"
+            .iter()
+            .cycle()
+            .take(len)
+            .copied()
+            .collect()
+    }
+
+    /// Shared buffer standing in for the `.gzi` file or the output.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(data)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn multi_member_output_round_trips() {
+        let data = fastq(BLOCK_SIZE * 2 + 12345);
+        let one = compress(&data, 1);
+        assert_eq!(decompress(&one), data);
+        // three members, each starting with the gzip magic
+        let magic = one.windows(3).filter(|w| *w == [0x1f, 0x8b, 0x08]).count();
+        assert!(magic >= 3);
+
+        // the same bytes whatever the thread count
+        assert_eq!(compress(&data, 3), one);
+    }
+
+    #[test]
+    fn empty_input_is_valid_gzip() {
+        let gz = compress(b"", 2);
+        assert_eq!(&gz[..2], &[0x1f, 0x8b]);
+        assert!(decompress(&gz).is_empty());
+    }
+
+    #[test]
+    fn bgzf_blocks_eof_and_index() {
+        // two full blocks of compressible data and one of incompressible bytes
+        let mut data = fastq(BGZF_BLOCK_SIZE * 2);
+        let mut x: u32 = 1;
+        data.extend((0..1000).map(|_| {
+            x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+            (x >> 24) as u8
+        }));
+        let gzi = Shared::default();
+        let mut w = ParGzWriter::bgzf(
+            Vec::new(),
+            Compression::fast(),
+            pool(2),
+            Some(Box::new(gzi.clone())),
+        );
+        w.write_all(&data).unwrap();
+        let out = w.finish().unwrap();
+
+        assert_eq!(decompress(&out), data);
+        assert!(out.ends_with(&BGZF_EOF));
+
+        // walk the blocks by their BSIZE fields
+        let mut starts = Vec::new();
+        let mut pos = 0;
+        while pos < out.len() {
+            assert_eq!(&out[pos..pos + 4], &[0x1f, 0x8b, 0x08, 0x04]);
+            assert_eq!(&out[pos + 12..pos + 14], b"BC");
+            starts.push(pos as u64);
+            pos += u16::from_le_bytes([out[pos + 16], out[pos + 17]]) as usize + 1;
+        }
+        assert_eq!(pos, out.len());
+        assert_eq!(starts.len(), 4);
+
+        // gzi: count, then (compressed, uncompressed) offsets of blocks 2 and 3
+        let gzi = gzi.0.borrow();
+        let words: Vec<u64> = gzi
+            .chunks(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        let block = BGZF_BLOCK_SIZE as u64;
+        assert_eq!(words, vec![2, starts[1], block, starts[2], 2 * block]);
+    }
+
+    #[test]
+    fn empty_bgzf_is_just_the_eof_block() {
+        let w = ParGzWriter::bgzf(Vec::new(), Compression::fast(), pool(1), None);
+        assert_eq!(w.finish().unwrap(), BGZF_EOF.to_vec());
+    }
+
+    /// Writer that fails like a full disk.
+    struct Full;
+
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("no space left"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finish_reports_inner_write_errors() {
+        // less than one block: nothing reaches `Full` before finish
+        let mut w = ParGzWriter::new(Full, Compression::fast(), pool(1));
+        w.write_all(b"@r1\nACGT\n+\nIIII\n").unwrap();
+        assert!(w.finish().is_err());
+
+        // a failing `.gzi` index fails the BGZF writer too
+        let mut w = ParGzWriter::bgzf(
+            Vec::new(),
+            Compression::fast(),
+            pool(1),
+            Some(Box::new(Full)),
+        );
+        w.write_all(b"@r1\nACGT\n+\nIIII\n").unwrap();
+        assert!(w.finish().is_err());
+    }
+
+    #[test]
+    fn failed_finish_is_not_retried_on_drop() {
+        let out = Shared::default();
+        let mut w = ParGzWriter::bgzf(
+            out.clone(),
+            Compression::fast(),
+            pool(1),
+            Some(Box::new(Full)),
+        );
+        w.write_all(b"@r1\nACGT\n+\nIIII\n").unwrap();
+        assert!(w.finish().is_err());
+
+        let out = out.0.borrow();
+        let eof = out
+            .windows(BGZF_EOF.len())
+            .filter(|w| *w == BGZF_EOF)
+            .count();
+        assert_eq!(eof, 1);
+        assert!(out.ends_with(&BGZF_EOF));
+    }
+}
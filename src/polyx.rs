@@ -0,0 +1,125 @@
+/// Instrument-ID prefixes of two-colour Illumina sequencers (NovaSeq, NextSeq,
+/// MiniSeq, iSeq), where a dark cycle is called as a high-quality G.
+const TWO_COLOUR_PREFIXES: [&str; 8] = ["A0", "NB", "NS", "NDX", "MN", "FS", "LH", "VH"];
+
+/// Return true when an Illumina read header names a two-colour instrument.
+/// The instrument is the first `:`-separated field of the id, or of the
+/// description for SRA-style headers (`@SRR123.1 A00123:8:HXXX:1:...`).
+pub fn is_two_colour(id: &str, desc: Option<&str>) -> bool {
+    let instrument_of = |s: &str| -> Option<bool> {
+        let mut fields = s.split(':');
+        let inst = fields.next()?;
+        // Illumina headers have at least instrument:run:flowcell:lane
+        if fields.count() < 3 {
+            return None;
+        }
+        Some(TWO_COLOUR_PREFIXES.iter().any(|p| inst.starts_with(p)))
+    };
+    instrument_of(id)
+        .or_else(|| desc.and_then(|d| d.split_whitespace().next().and_then(instrument_of)))
+        .unwrap_or(false)
+}
+
+/// Length of the homopolymer run of `base` at the start of `bases`.
+///
+/// Uses cutadapt's poly-A scoring: +1 per matching base and -2 per mismatch;
+/// the run ends at the highest-scoring position whose mismatch count is within
+/// `floor(len * max_mismatch_rate)`. Runs shorter than `min_len` give 0.
+fn run_len<'a>(
+    bases: impl Iterator<Item = &'a u8>,
+    base: u8,
+    min_len: usize,
+    max_mismatch_rate: f64,
+) -> usize {
+    let mut score = 0i64;
+    let mut best_score = 0i64;
+    let mut mismatches = 0usize;
+    let mut run = 0usize;
+    for (i, b) in bases.enumerate() {
+        if b.eq_ignore_ascii_case(&base) {
+            score += 1;
+        } else {
+            score -= 2;
+            mismatches += 1;
+        }
+        if score > best_score && mismatches as f64 <= ((i + 1) as f64 * max_mismatch_rate).floor() {
+            best_score = score;
+            run = i + 1;
+        }
+    }
+    if run >= min_len.max(1) {
+        run
+    } else {
+        0
+    }
+}
+
+/// Length of the homopolymer tail of `base` at the 3' end of `seq`.
+///
+/// Tails shorter than `min_len` are reported as 0.
+pub fn tail_len(seq: &[u8], base: u8, min_len: usize, max_mismatch_rate: f64) -> usize {
+    run_len(seq.iter().rev(), base, min_len, max_mismatch_rate)
+}
+
+/// Length of the homopolymer head of `base` at the 5' end of `seq`; see `tail_len`.
+pub fn head_len(seq: &[u8], base: u8, min_len: usize, max_mismatch_rate: f64) -> usize {
+    run_len(seq.iter(), base, min_len, max_mismatch_rate)
+}
+
+/// Longest homopolymer tail of any of A/C/G/T, as (base, length).
+pub fn longest_tail(seq: &[u8], min_len: usize, max_mismatch_rate: f64) -> Option<(u8, usize)> {
+    b"ACGT"
+        .iter()
+        .map(|b| (*b, tail_len(seq, *b, min_len, max_mismatch_rate)))
+        .filter(|(_, len)| *len > 0)
+        .max_by_key(|(_, len)| *len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{head_len, is_two_colour, longest_tail, tail_len};
+
+    #[test]
+    fn detects_two_colour_instruments() {
+        assert!(is_two_colour("A00123:8:HFWLKDSXX:1:1101:1000:1000", None));
+        assert!(is_two_colour(
+            "NB501234:12:HXXX:1:11101:100:100",
+            Some("1:N:0:1")
+        ));
+        assert!(!is_two_colour(
+            "M01234:12:000000000-AXXXX:1:1101:100:100",
+            None
+        ));
+        assert!(!is_two_colour("SEQ_000001", None));
+        // SRA-style header with the Illumina name in the description
+        assert!(is_two_colour(
+            "SRR123.1",
+            Some("A00123:8:HFWLKDSXX:1:1101:1000:1000 length=150")
+        ));
+    }
+
+    #[test]
+    fn trims_poly_g_with_sparse_mismatch() {
+        let mut seq = b"ACGTACGTTACA".to_vec();
+        seq.extend_from_slice(b"GGGGGGAGGGGGGGGG");
+        assert_eq!(tail_len(&seq, b'G', 10, 0.125), 16);
+        // a short tail is left alone
+        assert_eq!(tail_len(b"ACGTACGTACGGGG", b'G', 10, 0.125), 0);
+        // no tolerance stops at the mismatch
+        assert_eq!(tail_len(&seq, b'G', 5, 0.0), 9);
+    }
+
+    #[test]
+    fn longest_tail_picks_base() {
+        let mut seq = b"GATTACAGATTACA".to_vec();
+        seq.extend_from_slice(&[b'T'; 12]);
+        assert_eq!(longest_tail(&seq, 10, 0.125), Some((b'T', 12)));
+        assert_eq!(longest_tail(b"GATTACAGATTACA", 10, 0.125), None);
+    }
+
+    #[test]
+    fn head_len_scans_from_5prime() {
+        assert_eq!(head_len(b"TTTTTTTTTTTTACGATCGA", b'T', 10, 0.125), 12);
+        assert_eq!(head_len(b"ACGTTTTTTTTTTTT", b'T', 10, 0.125), 0);
+    }
+}
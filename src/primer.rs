@@ -0,0 +1,235 @@
+use bio::io::fasta;
+use std::error::Error;
+
+/// A primer from the primer FASTA; `pair` is the record name without its
+/// `_F`/`_R` (or `_fwd`/`_rev`) suffix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Primer {
+    pub pair: String,
+    pub seq: Vec<u8>,
+}
+
+/// Forward and reverse primers of an amplicon panel.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrimerSet {
+    pub forward: Vec<Primer>,
+    pub reverse: Vec<Primer>,
+}
+
+/// Primer pair found at the 5' ends of a read pair, with the primer length to
+/// remove from each mate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairMatch<'a> {
+    pub pair: &'a str,
+    pub len1: usize,
+    pub len2: usize,
+}
+
+impl PrimerSet {
+    /// Load primers from a FASTA file. Record names must end in `_F`/`_fwd`
+    /// (forward, matched on R1) or `_R`/`_rev` (reverse, matched on R2).
+    pub fn from_fasta(path: &str) -> Result<Self, Box<dyn Error>> {
+        let reader = fasta::Reader::from_file(path)?;
+        let mut records = Vec::new();
+        for result in reader.records() {
+            let rec = result?;
+            records.push((rec.id().to_string(), rec.seq().to_vec()));
+        }
+        Ok(Self::from_records(records)?)
+    }
+
+    /// Build the set from (name, sequence) records; see `from_fasta`.
+    pub fn from_records(records: Vec<(String, Vec<u8>)>) -> Result<Self, String> {
+        let mut set = PrimerSet::default();
+        for (name, seq) in records {
+            let lower = name.to_ascii_lowercase();
+            let (pair_len, forward) = if let Some(p) = lower.strip_suffix("_fwd") {
+                (p.len(), true)
+            } else if let Some(p) = lower.strip_suffix("_f") {
+                (p.len(), true)
+            } else if let Some(p) = lower.strip_suffix("_rev") {
+                (p.len(), false)
+            } else if let Some(p) = lower.strip_suffix("_r") {
+                (p.len(), false)
+            } else {
+                return Err(format!("primer '{}' must end in _F/_fwd or _R/_rev", name));
+            };
+            let primer = Primer {
+                pair: name[..pair_len].to_string(),
+                seq: seq.to_ascii_uppercase(),
+            };
+            if forward {
+                set.forward.push(primer);
+            } else {
+                set.reverse.push(primer);
+            }
+        }
+        if set.forward.is_empty() && set.reverse.is_empty() {
+            return Err("no primers found".to_string());
+        }
+        Ok(set)
+    }
+
+    /// Best primer (forward or reverse) anchored at the 5' end of a single read.
+    pub fn match_single(&self, read: &[u8], max_mismatches: usize) -> Option<&Primer> {
+        let fwd = best_match(read, &self.forward, max_mismatches);
+        let rev = best_match(read, &self.reverse, max_mismatches);
+        match (fwd, rev) {
+            (Some(f), Some(r)) => Some(if r.1 < f.1 { r.0 } else { f.0 }),
+            (Some(f), None) => Some(f.0),
+            (None, Some(r)) => Some(r.0),
+            (None, None) => None,
+        }
+    }
+
+    /// Match a read pair: the forward primer on R1 and the reverse primer of the
+    /// same pair on R2, or the other way round for mixed-orientation libraries.
+    /// Returns None unless both mates carry primers of the same pair.
+    pub fn match_pair(&self, r1: &[u8], r2: &[u8], max_mismatches: usize) -> Option<PairMatch<'_>> {
+        match (
+            match_oriented(r1, r2, &self.forward, &self.reverse, max_mismatches),
+            match_oriented(r1, r2, &self.reverse, &self.forward, max_mismatches),
+        ) {
+            (Some(a), Some(b)) => Some(if b.1 < a.1 { b.0 } else { a.0 }),
+            (Some(a), None) => Some(a.0),
+            (None, Some(b)) => Some(b.0),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Match `first` primers on R1 and the same-pair `second` primers on R2,
+/// returning the match and its total mismatches.
+fn match_oriented<'a>(
+    r1: &[u8],
+    r2: &[u8],
+    first: &'a [Primer],
+    second: &[Primer],
+    max_mismatches: usize,
+) -> Option<(PairMatch<'a>, usize)> {
+    let (p1, mm1) = best_match(r1, first, max_mismatches)?;
+    let (p2, mm2) = second
+        .iter()
+        .filter(|p| p.pair == p1.pair)
+        .filter_map(|p| best_match(r2, std::slice::from_ref(p), max_mismatches))
+        .min_by_key(|(_, mm)| *mm)?;
+    Some((
+        PairMatch {
+            pair: &p1.pair,
+            len1: p1.seq.len(),
+            len2: p2.seq.len(),
+        },
+        mm1 + mm2,
+    ))
+}
+
+/// True when read base `read` is compatible with IUPAC code `primer`.
+pub fn iupac_matches(read: u8, primer: u8) -> bool {
+    let r = read.to_ascii_uppercase();
+    let allowed: &[u8] = match primer.to_ascii_uppercase() {
+        b'A' => b"A",
+        b'C' => b"C",
+        b'G' => b"G",
+        b'T' | b'U' => b"TU",
+        b'R' => b"AG",
+        b'Y' => b"CTU",
+        b'S' => b"GC",
+        b'W' => b"ATU",
+        b'K' => b"GTU",
+        b'M' => b"AC",
+        b'B' => b"CGTU",
+        b'D' => b"AGTU",
+        b'H' => b"ACTU",
+        b'V' => b"ACG",
+        b'N' => return true,
+        _ => return false,
+    };
+    allowed.contains(&r)
+}
+
+/// Primer with the fewest mismatches anchored at the start of `read` (first one
+/// on ties), with its mismatch count.
+fn best_match<'a>(
+    read: &[u8],
+    primers: &'a [Primer],
+    max_mismatches: usize,
+) -> Option<(&'a Primer, usize)> {
+    let mut best: Option<(&Primer, usize)> = None;
+    for p in primers {
+        if p.seq.is_empty() || read.len() < p.seq.len() {
+            continue;
+        }
+        let mut mm = 0usize;
+        for (r, q) in read.iter().zip(&p.seq) {
+            if !iupac_matches(*r, *q) {
+                mm += 1;
+                if mm > max_mismatches {
+                    break;
+                }
+            }
+        }
+        if mm <= max_mismatches && best.is_none_or(|(_, b)| mm < b) {
+            best = Some((p, mm));
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{iupac_matches, PrimerSet};
+
+    fn set() -> PrimerSet {
+        // 16S V4 primers 515F / 806R
+        PrimerSet::from_records(vec![
+            ("V4_F".to_string(), b"GTGYCAGCMGCCGCGGTAA".to_vec()),
+            ("V4_R".to_string(), b"GGACTACNVGGGTWTCTAAT".to_vec()),
+            ("ITS_fwd".to_string(), b"CTTGGTCATTTAGAGGAAGTAA".to_vec()),
+            ("ITS_rev".to_string(), b"GCTGCGTTCTTCATCGATGC".to_vec()),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn iupac_codes() {
+        assert!(iupac_matches(b'C', b'Y'));
+        assert!(iupac_matches(b't', b'Y'));
+        assert!(!iupac_matches(b'A', b'Y'));
+        assert!(iupac_matches(b'G', b'N'));
+        assert!(!iupac_matches(b'T', b'V'));
+    }
+
+    #[test]
+    fn matches_degenerate_primer_pair() {
+        let primers = set();
+        let r1 = b"GTGCCAGCAGCCGCGGTAATACGGAGGGTGCAAGCG";
+        let r2 = b"GGACTACCAGGGTATCTAATCCTGTTTGCTCCCCAC";
+        let m = primers.match_pair(r1, r2, 0).unwrap();
+        assert_eq!(m.pair, "V4");
+        assert_eq!((m.len1, m.len2), (19, 20));
+
+        // swapped orientation is accepted too
+        let m = primers.match_pair(r2, r1, 0).unwrap();
+        assert_eq!((m.len1, m.len2), (20, 19));
+    }
+
+    #[test]
+    fn mismatch_limit_and_pair_consistency() {
+        let primers = set();
+        // two mismatches in the forward primer
+        let r1 = b"GTGCCAGCAGCCGCGCAAAAACGGAGGGTGCAAGCG";
+        let r2 = b"GGACTACCAGGGTATCTAATCCTGTTTGCTCCCCAC";
+        assert!(primers.match_pair(r1, r2, 1).is_none());
+        assert!(primers.match_pair(r1, r2, 2).is_some());
+
+        // forward primer of one pair with the reverse primer of another
+        let its_r2 = b"GCTGCGTTCTTCATCGATGCAAAAAAAAAAAAAAAA";
+        assert!(primers.match_pair(r1, its_r2, 2).is_none());
+        assert_eq!(primers.match_single(its_r2, 0).unwrap().pair, "ITS");
+    }
+
+    #[test]
+    fn rejects_unnamed_orientation() {
+        assert!(PrimerSet::from_records(vec![("p1".to_string(), b"ACGT".to_vec())]).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use bio::io::fastq;
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::borrow::Cow;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Number of leading reads sampled to detect the encoding.
+pub const DETECT_READS: usize = 10000;
+
+/// Quality encoding of the input, selectable with `--phred`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// Detect from the first reads of the input
+    #[default]
+    Auto,
+    /// Phred+33 (Sanger, Illumina 1.8+)
+    #[value(name = "33")]
+    Phred33,
+    /// Phred+64 (Illumina 1.3-1.7)
+    #[value(name = "64")]
+    Phred64,
+}
+
+impl Encoding {
+    /// ASCII offset of a fixed encoding; None for `Auto`.
+    pub fn offset(self) -> Option<u8> {
+        match self {
+            Encoding::Auto => None,
+            Encoding::Phred33 => Some(33),
+            Encoding::Phred64 => Some(64),
+        }
+    }
+}
+
+/// Guess the ASCII offset (33 or 64) of sampled quality strings. Characters
+/// below `;` only occur in Phred+33, so does data that never goes above `J`
+/// (Q41). Phred+64 is only chosen when characters above `J` come with none
+/// below `@` (Q0); `None` means the sample fits both, e.g. Phred+33 from Q42+
+/// instruments or reads that were already quality-filtered.
+pub fn detect_offset(quals: &[Vec<u8>]) -> Option<u8> {
+    let mut chars = quals.iter().flatten();
+    let Some(first) = chars.next() else {
+        return Some(33);
+    };
+    let (min, max) = chars.fold((*first, *first), |(lo, hi), q| (lo.min(*q), hi.max(*q)));
+    if min < b';' || max <= b'J' {
+        Some(33)
+    } else if min >= b'@' {
+        Some(64)
+    } else {
+        None
+    }
+}
+
+/// Re-encode a quality string from `from` to `to` offset; scores below zero
+/// (Solexa-style) are clamped to Q0.
+pub fn convert(qual: &[u8], from: u8, to: u8) -> Vec<u8> {
+    qual.iter()
+        .map(|q| q.saturating_sub(from).saturating_add(to))
+        .collect()
+}
+
+/// The record with Phred+33 qualities, borrowed when already Phred+33.
+pub fn to_phred33(rec: &fastq::Record, offset: u8) -> Cow<'_, fastq::Record> {
+    if offset == 33 {
+        return Cow::Borrowed(rec);
+    }
+    Cow::Owned(fastq::Record::with_attrs(
+        rec.id(),
+        rec.desc(),
+        rec.seq(),
+        &convert(rec.qual(), offset, 33),
+    ))
+}
+
+/// Quality bins for `--qual-bins`: each `(low, high, value)` maps Phred scores
+/// `low..=high` to `value`; scores outside every bin are kept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QualBins {
+    bins: Vec<(u8, u8, u8)>,
+}
+
+/// Illumina's 8-level binning (2-9 -> 6, 10-19 -> 15, ..., 40+ -> 40).
+const ILLUMINA8: [(u8, u8, u8); 7] = [
+    (2, 9, 6),
+    (10, 19, 15),
+    (20, 24, 22),
+    (25, 29, 27),
+    (30, 34, 33),
+    (35, 39, 37),
+    (40, 93, 40),
+];
+
+impl FromStr for QualBins {
+    type Err = String;
+
+    /// `illumina8`, or custom bins such as `0-9:5,10-29:20,30-:35`
+    /// (`low-` is open-ended).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("illumina8") {
+            return Ok(QualBins {
+                bins: ILLUMINA8.to_vec(),
+            });
+        }
+        let invalid = |part: &str| format!("invalid quality bin '{}'", part);
+        let mut bins = Vec::new();
+        for part in s.split(',') {
+            let (range, value) = part.split_once(':').ok_or_else(|| invalid(part))?;
+            let (low, high) = range.split_once('-').ok_or_else(|| invalid(part))?;
+            let low: u8 = low.trim().parse().map_err(|_| invalid(part))?;
+            let high: u8 = match high.trim() {
+                "" => 93,
+                h => h.parse().map_err(|_| invalid(part))?,
+            };
+            let value: u8 = value.trim().parse().map_err(|_| invalid(part))?;
+            if low > high || value > 93 {
+                return Err(invalid(part));
+            }
+            bins.push((low, high, value));
+        }
+        Ok(QualBins { bins })
+    }
+}
+
+impl QualBins {
+    /// Binned Phred score of `q`.
+    pub fn bin(&self, q: u8) -> u8 {
+        self.bins
+            .iter()
+            .find(|(low, high, _)| (*low..=*high).contains(&q))
+            .map_or(q, |b| b.2)
+    }
+}
+
+/// Bytes of quality strings sampled by `QualOutput` to measure the effect of binning.
+const SAMPLE_BYTES: usize = 1 << 20;
+
+/// Quality handling on output: optional binning, then encoding at `offset`.
+/// Keeps the first quality strings written, before and after binning, to
+/// measure how much smaller they compress.
+#[derive(Clone, Debug)]
+pub struct QualOutput {
+    offset: u8,
+    bins: Option<QualBins>,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+/// Gzip-compressed size of the sampled quality strings before and after binning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BinningSizes {
+    /// Uncompressed size of the sample (qualities and newlines)
+    pub sampled: usize,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl QualOutput {
+    pub fn new(offset: u8, bins: Option<QualBins>) -> Self {
+        QualOutput {
+            offset,
+            bins,
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+
+    /// Phred+33 `qual` as it is written.
+    pub fn apply<'a>(&mut self, qual: &'a [u8]) -> Cow<'a, [u8]> {
+        let Some(bins) = &self.bins else {
+            if self.offset == 33 {
+                return Cow::Borrowed(qual);
+            }
+            return Cow::Owned(convert(qual, 33, self.offset));
+        };
+        let sample = self.before.len() < SAMPLE_BYTES;
+        let mut out = Vec::with_capacity(qual.len());
+        for q in qual {
+            let score = q.saturating_sub(33).min(93);
+            out.push(bins.bin(score).min(93) + self.offset);
+            if sample {
+                self.before.push(score + self.offset);
+            }
+        }
+        if sample {
+            self.before.push(b'\n');
+            self.after.extend_from_slice(&out);
+            self.after.push(b'\n');
+        }
+        Cow::Owned(out)
+    }
+
+    /// Compress the sampled quality strings to measure the saving from
+    /// binning; None without `--qual-bins`.
+    pub fn binning_sizes(&self) -> Option<BinningSizes> {
+        self.bins.as_ref()?;
+        Some(BinningSizes {
+            sampled: self.before.len(),
+            before: gzip_len(&self.before),
+            after: gzip_len(&self.after),
+        })
+    }
+}
+
+fn gzip_len(data: &[u8]) -> usize {
+    let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+    enc.write_all(data)
+        .and_then(|_| enc.finish())
+        .expect("compressing to memory")
+        .len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, detect_offset, to_phred33, QualBins, QualOutput};
+    use bio::io::fastq;
+
+    #[test]
+    fn detects_offset_from_quality_range() {
+        // Illumina 1.8+: '#' (Q2) .. 'J' (Q41)
+        assert_eq!(detect_offset(&[b"##,:FFJ".to_vec()]), Some(33));
+        // Illumina 1.5: 'B' (Q2) .. 'h' (Q40)
+        assert_eq!(
+            detect_offset(&[b"BBTa".to_vec(), b"hhh".to_vec()]),
+            Some(64)
+        );
+        // only high Phred+33 scores: no sign of Phred+64
+        assert_eq!(detect_offset(&[b"FFFFJ".to_vec()]), Some(33));
+        assert_eq!(detect_offset(&[]), Some(33));
+        // filtered Phred+33 with Q42+ ('K'): fits both, no guess
+        assert_eq!(detect_offset(&[b"<<FFJK".to_vec()]), None);
+    }
+
+    #[test]
+    fn converts_between_offsets() {
+        assert_eq!(convert(b"Bh", 64, 33), b"#I".to_vec());
+        assert_eq!(convert(b"#I", 33, 64), b"Bh".to_vec());
+        // Solexa negative scores clamp to Q0
+        assert_eq!(convert(b";", 64, 33), b"!".to_vec());
+
+        let rec = fastq::Record::with_attrs("r", None, b"AC", b"Bh");
+        assert_eq!(to_phred33(&rec, 64).qual(), b"#I");
+        assert_eq!(to_phred33(&rec, 33).qual(), b"Bh");
+    }
+
+    #[test]
+    fn illumina8_and_custom_bins() {
+        let bins: QualBins = "illumina8".parse().unwrap();
+        assert_eq!(bins.bin(0), 0);
+        assert_eq!(bins.bin(2), 6);
+        assert_eq!(bins.bin(19), 15);
+        assert_eq!(bins.bin(31), 33);
+        assert_eq!(bins.bin(41), 40);
+
+        let custom: QualBins = "0-9:5,10-29:20,30-:35".parse().unwrap();
+        assert_eq!(custom.bin(3), 5);
+        assert_eq!(custom.bin(25), 20);
+        assert_eq!(custom.bin(45), 35);
+        assert!("10-5:7".parse::<QualBins>().is_err());
+        assert!("0-9".parse::<QualBins>().is_err());
+    }
+
+    #[test]
+    fn output_bins_then_encodes() {
+        let mut out = QualOutput::new(64, Some("illumina8".parse().unwrap()));
+        // Q2, Q12, Q38, Q41 in Phred+33
+        assert_eq!(out.apply(b"#-GJ").as_ref(), b"FOeh");
+        assert_eq!(out.binning_sizes().unwrap().sampled, 5);
+
+        let mut plain = QualOutput::new(33, None);
+        assert_eq!(plain.apply(b"#-GJ").as_ref(), b"#-GJ");
+        assert!(plain.binning_sizes().is_none());
+    }
+
+    #[test]
+    fn binning_shrinks_compressed_qualities() {
+        let mut out = QualOutput::new(33, Some("illumina8".parse().unwrap()));
+        let mut x: u32 = 7;
+        for _ in 0..1000 {
+            let qual: Vec<u8> = (0..100)
+                .map(|_| {
+                    x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+                    33 + 2 + (x >> 24) as u8 % 40
+                })
+                .collect();
+            out.apply(&qual);
+        }
+        let sizes = out.binning_sizes().unwrap();
+        assert_eq!(sizes.sampled, 101_000);
+        assert!(sizes.after < sizes.before);
+    }
+}
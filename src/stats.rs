@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+/// Number of reads modified or dropped by each optional trimming/filter stage.
+#[derive(Debug, Default, Clone)]
+pub struct TrimStats {
+    pub adapter_trimmed: u64,
+    /// reads searched for a poly-G tail (`--poly-g` or a two-colour instrument)
+    pub poly_g_checked: u64,
+    pub poly_g_trimmed: u64,
+    pub poly_x_trimmed: u64,
+    pub poly_a_trimmed: u64,
+    pub poly_a_bases: u64,
+    pub poly_t_trimmed: u64,
+    pub poly_t_bases: u64,
+    pub n_trimmed: u64,
+    pub n_trim_dropped: u64,
+    pub n_split: u64,
+    pub n_split_dropped: u64,
+    pub max_n_dropped: u64,
+    pub max_n_frac_dropped: u64,
+    pub low_complexity_dropped: u64,
+    pub ee_truncated: u64,
+    pub ee_truncated_dropped: u64,
+    pub max_ee_dropped: u64,
+    pub max_ee_rate_dropped: u64,
+    pub mean_qual_dropped: u64,
+    pub unqualified_dropped: u64,
+    pub max_len_dropped: u64,
+    pub masked_reads: u64,
+    pub masked_bases: u64,
+}
+
+impl TrimStats {
+    /// Add the counts of `other` (e.g. from another worker thread).
+    pub fn merge(&mut self, other: &TrimStats) {
+        self.adapter_trimmed += other.adapter_trimmed;
+        self.poly_g_checked += other.poly_g_checked;
+        self.poly_g_trimmed += other.poly_g_trimmed;
+        self.poly_x_trimmed += other.poly_x_trimmed;
+        self.poly_a_trimmed += other.poly_a_trimmed;
+        self.poly_a_bases += other.poly_a_bases;
+        self.poly_t_trimmed += other.poly_t_trimmed;
+        self.poly_t_bases += other.poly_t_bases;
+        self.n_trimmed += other.n_trimmed;
+        self.n_trim_dropped += other.n_trim_dropped;
+        self.n_split += other.n_split;
+        self.n_split_dropped += other.n_split_dropped;
+        self.max_n_dropped += other.max_n_dropped;
+        self.max_n_frac_dropped += other.max_n_frac_dropped;
+        self.low_complexity_dropped += other.low_complexity_dropped;
+        self.ee_truncated += other.ee_truncated;
+        self.ee_truncated_dropped += other.ee_truncated_dropped;
+        self.max_ee_dropped += other.max_ee_dropped;
+        self.max_ee_rate_dropped += other.max_ee_rate_dropped;
+        self.mean_qual_dropped += other.mean_qual_dropped;
+        self.unqualified_dropped += other.unqualified_dropped;
+        self.max_len_dropped += other.max_len_dropped;
+        self.masked_reads += other.masked_reads;
+        self.masked_bases += other.masked_bases;
+    }
+}
+
+/// Counts of integer values (e.g. insert sizes), kept sorted by value.
+#[derive(Debug, Default, Clone)]
+pub struct Histogram {
+    counts: BTreeMap<usize, u64>,
+}
+
+impl Histogram {
+    pub fn add(&mut self, value: usize) {
+        *self.counts.entry(value).or_insert(0) += 1;
+    }
+
+    /// Number of values added.
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        let sum: f64 = self.counts.iter().map(|(v, c)| *v as f64 * *c as f64).sum();
+        Some(sum / total as f64)
+    }
+
+    /// Lower median of the added values.
+    pub fn median(&self) -> Option<usize> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        let mid = total.div_ceil(2);
+        let mut seen = 0u64;
+        for (v, c) in &self.counts {
+            seen += c;
+            if seen >= mid {
+                return Some(*v);
+            }
+        }
+        None
+    }
+
+    /// (value, count) pairs in ascending value order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.counts.iter().map(|(v, c)| (*v, *c))
+    }
+
+    /// Print the summary and the full histogram to stdout, prefixing each line
+    /// with `label` (e.g. "insert size").
+    pub fn print(&self, label: &str) {
+        println!("{} count: {}", label, self.total());
+        if let (Some(mean), Some(median)) = (self.mean(), self.median()) {
+            println!("{} mean: {:.1}", label, mean);
+            println!("{} median: {}", label, median);
+        }
+        for (v, c) in self.iter() {
+            println!("{} {}: {}", label, v, c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Histogram, TrimStats};
+
+    #[test]
+    fn trim_stats_merge_adds_counts() {
+        let mut a = TrimStats {
+            adapter_trimmed: 2,
+            masked_bases: 5,
+            ..Default::default()
+        };
+        let b = TrimStats {
+            adapter_trimmed: 1,
+            max_ee_dropped: 3,
+            ..Default::default()
+        };
+        a.merge(&b);
+        assert_eq!(a.adapter_trimmed, 3);
+        assert_eq!(a.max_ee_dropped, 3);
+        assert_eq!(a.masked_bases, 5);
+    }
+
+    #[test]
+    fn histogram_summary_values() {
+        let mut h = Histogram::default();
+        assert_eq!(h.mean(), None);
+        for v in [100, 150, 150, 200] {
+            h.add(v);
+        }
+        assert_eq!(h.total(), 4);
+        assert_eq!(h.mean(), Some(150.0));
+        assert_eq!(h.median(), Some(150));
+        assert_eq!(
+            h.iter().collect::<Vec<_>>(),
+            vec![(100, 1), (150, 2), (200, 1)]
+        );
+    }
+}
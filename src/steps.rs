@@ -0,0 +1,233 @@
+use std::str::FromStr;
+
+/// One Trimmomatic-style trimming step.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// `LEADING:<q>` remove 5' bases below quality `q`
+    Leading(u8),
+    /// `TRAILING:<q>` remove 3' bases below quality `q`
+    Trailing(u8),
+    /// `SLIDINGWINDOW:<w>:<q>` scan from the 5' end and cut at the first window
+    /// whose average quality is below `q`
+    SlidingWindow { window: usize, quality: u8 },
+    /// `HEADCROP:<n>` remove the first `n` bases
+    HeadCrop(usize),
+    /// `CROP:<n>` keep at most the first `n` bases
+    Crop(usize),
+    /// `MINLEN:<n>` drop the read if shorter than `n`
+    MinLen(usize),
+}
+
+/// An ordered list of steps, parsed from e.g.
+/// `LEADING:3 TRAILING:3 SLIDINGWINDOW:4:15 HEADCROP:10 MINLEN:36`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pipeline {
+    pub steps: Vec<Step>,
+}
+
+fn parse_num<T: FromStr>(step: &str, value: Option<&str>) -> Result<T, String> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| format!("invalid or missing value in step '{}'", step))
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or("").to_ascii_uppercase();
+        let step = match name.as_str() {
+            "LEADING" => Step::Leading(parse_num(s, parts.next())?),
+            "TRAILING" => Step::Trailing(parse_num(s, parts.next())?),
+            "SLIDINGWINDOW" => {
+                let window: usize = parse_num(s, parts.next())?;
+                let quality = parse_num(s, parts.next())?;
+                if window == 0 {
+                    return Err(format!("window size must be positive in step '{}'", s));
+                }
+                Step::SlidingWindow { window, quality }
+            }
+            "HEADCROP" => Step::HeadCrop(parse_num(s, parts.next())?),
+            "CROP" => Step::Crop(parse_num(s, parts.next())?),
+            "MINLEN" => Step::MinLen(parse_num(s, parts.next())?),
+            _ => return Err(format!("unsupported step '{}'", s)),
+        };
+        if parts.next().is_some() {
+            return Err(format!("too many values in step '{}'", s));
+        }
+        Ok(step)
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let steps = s
+            .split_whitespace()
+            .map(Step::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        if steps.is_empty() {
+            return Err("no trimming steps given".to_string());
+        }
+        Ok(Pipeline { steps })
+    }
+}
+
+impl Step {
+    /// Apply the step to `qual[start..end]` (Phred+33). Returns the new range,
+    /// or None when the read is dropped.
+    fn apply(&self, qual: &[u8], start: usize, end: usize) -> Option<(usize, usize)> {
+        let q = |i: usize| qual[i].saturating_sub(33);
+        match *self {
+            Step::Leading(thr) => {
+                let mut s = start;
+                while s < end && q(s) < thr {
+                    s += 1;
+                }
+                Some((s, end))
+            }
+            Step::Trailing(thr) => {
+                let mut e = end;
+                while e > start && q(e - 1) < thr {
+                    e -= 1;
+                }
+                Some((start, e))
+            }
+            Step::SlidingWindow { window, quality } => {
+                let len = end - start;
+                // Trimmomatic drops reads shorter than the window
+                if len < window {
+                    return None;
+                }
+                // and compares window sums against quality * window
+                let win = window;
+                let required = quality as u32 * win as u32;
+                let mut total: u32 = (start..start + win).map(|i| q(i) as u32).sum();
+                if total < required {
+                    return None;
+                }
+                let mut keep = len;
+                for i in 0..len - win {
+                    total = total - q(start + i) as u32 + q(start + i + win) as u32;
+                    if total < required {
+                        keep = i + win;
+                        break;
+                    }
+                }
+                // then trim back to the last base of the kept part reaching `quality`;
+                // as in Trimmomatic, the first base is never checked
+                while keep > 1 && q(start + keep - 1) < quality {
+                    keep -= 1;
+                }
+                Some((start, start + keep))
+            }
+            Step::HeadCrop(n) => Some(((start + n).min(end), end)),
+            Step::Crop(n) => Some((start, end.min(start + n))),
+            Step::MinLen(n) => {
+                if end - start < n {
+                    None
+                } else {
+                    Some((start, end))
+                }
+            }
+        }
+    }
+}
+
+impl Pipeline {
+    /// Run all steps in order on a quality string (Phred+33). Returns the kept
+    /// `(start, end)` range, or None if a step dropped the read or nothing is left.
+    pub fn apply(&self, qual: &[u8]) -> Option<(usize, usize)> {
+        let mut range = (0, qual.len());
+        for step in &self.steps {
+            range = step.apply(qual, range.0, range.1)?;
+        }
+        if range.0 >= range.1 {
+            return None;
+        }
+        Some(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pipeline, Step};
+
+    fn phred(vals: &[u8]) -> Vec<u8> {
+        vals.iter().map(|q| q + 33).collect()
+    }
+
+    #[test]
+    fn parses_trimmomatic_step_string() {
+        let p: Pipeline = "LEADING:3 TRAILING:3 SLIDINGWINDOW:4:15 HEADCROP:10 MINLEN:36"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            p.steps,
+            vec![
+                Step::Leading(3),
+                Step::Trailing(3),
+                Step::SlidingWindow {
+                    window: 4,
+                    quality: 15
+                },
+                Step::HeadCrop(10),
+                Step::MinLen(36),
+            ]
+        );
+        assert!("ILLUMINACLIP:adapters.fa:2:30:10"
+            .parse::<Pipeline>()
+            .is_err());
+        assert!("SLIDINGWINDOW:4".parse::<Pipeline>().is_err());
+        assert!("".parse::<Pipeline>().is_err());
+    }
+
+    #[test]
+    fn sliding_window_cuts_at_first_failing_window_from_5prime() {
+        // the low block in the middle ends the read, unlike the edge/window modes
+        // which only look at the ends
+        let qual = phred(&[30, 30, 30, 30, 30, 30, 5, 5, 5, 30, 30, 30, 30, 30]);
+        let p: Pipeline = "SLIDINGWINDOW:4:15".parse().unwrap();
+        // window starting at 5 (30,5,5,5) is the first to fail -> keep 8, then
+        // trim back the trailing Q5 bases -> 6
+        assert_eq!(p.apply(&qual), Some((0, 6)));
+
+        // first window already failing drops the read
+        let bad = phred(&[2, 2, 2, 2, 30, 30, 30, 30]);
+        assert_eq!(p.apply(&bad), None);
+    }
+
+    #[test]
+    fn sliding_window_trim_back_stops_at_the_first_base() {
+        // the only window passes on base 0; trimming back the bases below Q10
+        // stops there, leaving one base rather than an empty read
+        let p: Pipeline = "SLIDINGWINDOW:4:10".parse().unwrap();
+        assert_eq!(p.apply(&phred(&[40, 2, 2, 2])), Some((0, 1)));
+
+        // a cut after the first window is trimmed back the same way
+        let p: Pipeline = "SLIDINGWINDOW:2:15".parse().unwrap();
+        assert_eq!(p.apply(&phred(&[40, 2, 2, 2, 2])), Some((0, 1)));
+    }
+
+    #[test]
+    fn sliding_window_drops_reads_shorter_than_the_window() {
+        let p: Pipeline = "SLIDINGWINDOW:4:15".parse().unwrap();
+        assert_eq!(p.apply(&phred(&[40, 40, 40])), None);
+        assert_eq!(p.apply(&phred(&[40, 40, 40, 40])), Some((0, 4)));
+        // also after an earlier step shortened the read
+        let p: Pipeline = "CROP:3 SLIDINGWINDOW:4:15".parse().unwrap();
+        assert_eq!(p.apply(&phred(&[40; 10])), None);
+    }
+
+    #[test]
+    fn steps_run_in_order() {
+        let qual = phred(&[2, 2, 30, 30, 30, 30, 30, 30, 30, 30, 2, 2]);
+        let p: Pipeline = "LEADING:3 TRAILING:3 HEADCROP:2 CROP:5".parse().unwrap();
+        assert_eq!(p.apply(&qual), Some((4, 9)));
+
+        let p: Pipeline = "LEADING:3 TRAILING:3 MINLEN:9".parse().unwrap();
+        assert_eq!(p.apply(&qual), None);
+    }
+}
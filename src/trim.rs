@@ -1,122 +1,303 @@
+use clap::ValueEnum;
+
+/// Quality-trimming algorithm selectable with `--trim-mode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TrimMode {
+    /// Stop at the first base (or window, see `--window`) reaching the threshold
+    #[default]
+    Edge,
+    /// BWA/cutadapt partial-sum algorithm (same result as `cutadapt -q`)
+    Mott,
+}
+
+/// Fixed-position cropping of one mate, applied before quality trimming.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Crop {
+    /// bases removed from the 5' end
+    pub head: usize,
+    /// bases removed from the 3' end
+    pub tail: usize,
+    /// hard cap on the length left after the head/tail crop
+    pub max_len: Option<usize>,
+}
+
+impl Crop {
+    /// Range of a read of length `len` kept after cropping (empty when the
+    /// crops cover the whole read).
+    pub fn range(&self, len: usize) -> (usize, usize) {
+        let start = self.head.min(len);
+        let mut end = len.saturating_sub(self.tail).max(start);
+        if let Some(max) = self.max_len {
+            end = end.min(start + max);
+        }
+        (start, end)
+    }
+}
+
 pub fn trim_record(
     qual: &[u8],
     seq: &[u8],
     qual_thr: u8,
     min_len: usize,
     window: usize,
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    trim_record_ends(qual, seq, (qual_thr, window), (qual_thr, window), min_len)
+}
+
+/// Edge trimming with separate `(threshold, window)` settings for the 5' and
+/// 3' ends; a threshold of 0 leaves that end untouched.
+pub fn trim_record_ends(
+    qual: &[u8],
+    seq: &[u8],
+    front: (u8, usize),
+    back: (u8, usize),
+    min_len: usize,
 ) -> Option<(Vec<u8>, Vec<u8>)> {
     // Phred+33 assumed
     if qual.is_empty() || seq.is_empty() {
         return None;
     }
-    let mut start_idx = 0usize;
-    let mut end_idx = qual.len().saturating_sub(1);
+    let start_idx = front_index(qual, front.0, front.1);
+    let end_idx = back_index(qual, back.0, back.1);
 
-    if window <= 1 {
-        // existing single-base trimming
-        while start_idx <= end_idx {
-            let q = qual[start_idx].saturating_sub(33);
-            if q >= qual_thr {
-                break;
-            }
-            start_idx += 1;
-        }
+    if start_idx >= end_idx {
+        return None;
+    }
+    let trimmed_len = end_idx - start_idx;
+    if trimmed_len < min_len {
+        return None;
+    }
 
-        while end_idx >= start_idx {
-            let q = qual[end_idx].saturating_sub(33);
-            if q >= qual_thr {
-                break;
-            }
-            if end_idx == 0 {
-                break;
-            }
-            end_idx = end_idx.saturating_sub(1);
+    let seq_slice = &seq[start_idx..end_idx];
+    let qual_slice = &qual[start_idx..end_idx];
+    Some((seq_slice.to_vec(), qual_slice.to_vec()))
+}
+
+/// Integer window averages (Phred+33), one per window start; None when the
+/// window is 1 or longer than the read (single-base trimming is used then).
+fn window_means(qual: &[u8], window: usize) -> Option<Vec<u32>> {
+    let n = qual.len();
+    if window <= 1 || window > n {
+        return None;
+    }
+    // compute integer scores (Phred-33) and prefix sums for fast window sums
+    let mut ps: Vec<u32> = Vec::with_capacity(n + 1);
+    ps.push(0);
+    for b in qual {
+        ps.push(ps.last().unwrap() + b.saturating_sub(33) as u32);
+    }
+    Some(
+        (0..=n - window)
+            .map(|i| (ps[i + window] - ps[i]) / window as u32)
+            .collect(),
+    )
+}
+
+/// Start of the kept read: the first base (or window start) reaching `qual_thr`,
+/// or `qual.len()` when there is none.
+fn front_index(qual: &[u8], qual_thr: u8, window: usize) -> usize {
+    let thr = qual_thr as u32;
+    match window_means(qual, window) {
+        Some(means) => means.iter().position(|m| *m >= thr).unwrap_or(qual.len()),
+        None => qual
+            .iter()
+            .position(|q| q.saturating_sub(33) >= qual_thr)
+            .unwrap_or(qual.len()),
+    }
+}
+
+/// End (exclusive) of the kept read: just past the last base (or window)
+/// reaching `qual_thr`, or 0 when there is none.
+fn back_index(qual: &[u8], qual_thr: u8, window: usize) -> usize {
+    let thr = qual_thr as u32;
+    match window_means(qual, window) {
+        Some(means) => means
+            .iter()
+            .rposition(|m| *m >= thr)
+            .map_or(0, |i| i + window),
+        None => qual
+            .iter()
+            .rposition(|q| q.saturating_sub(33) >= qual_thr)
+            .map_or(0, |i| i + 1),
+    }
+}
+
+/// Replace bases with quality below `qual_thr` (Phred+33) by `N`, keeping the
+/// read length and qualities. Returns the number of bases masked.
+pub fn mask_low_quality(seq: &mut [u8], qual: &[u8], qual_thr: u8) -> usize {
+    let mut masked = 0;
+    for (b, q) in seq.iter_mut().zip(qual) {
+        if q.saturating_sub(33) < qual_thr && *b != b'N' {
+            *b = b'N';
+            masked += 1;
         }
-    } else {
-        // sliding-window trimming by average quality (fallback to single-base if window > read len)
-        let n = qual.len();
-        if window > n {
-            // fallback to single-base trimming
-            while start_idx <= end_idx {
-                let q = qual[start_idx].saturating_sub(33);
-                if q >= qual_thr {
-                    break;
-                }
-                start_idx += 1;
-            }
+    }
+    masked
+}
 
-            while end_idx >= start_idx {
-                let q = qual[end_idx].saturating_sub(33);
-                if q >= qual_thr {
-                    break;
-                }
-                if end_idx == 0 {
-                    break;
-                }
-                end_idx = end_idx.saturating_sub(1);
-            }
-        } else {
-            // compute integer scores (Phred-33) and prefix sums for fast window sums
-            let scores: Vec<u32> = qual.iter().map(|b| b.saturating_sub(33) as u32).collect();
-            let mut ps: Vec<u32> = Vec::with_capacity(scores.len() + 1);
-            ps.push(0);
-            for s in &scores {
-                ps.push(ps.last().unwrap() + *s);
-            }
+/// BWA/cutadapt quality-trimming index (Phred+33).
+///
+/// From each end, `cutoff - q` is summed base by base until the partial sum
+/// drops below zero; the cut is placed where the sum was largest, so a few good
+/// bases inside a bad tail do not stop trimming. A cutoff of 0 leaves that end
+/// alone. Returns the kept `(start, stop)` range, `(0, 0)` if nothing is kept;
+/// this is a line-by-line port of cutadapt's `quality_trim_index`.
+pub fn mott_trim_index(qual: &[u8], cutoff_front: u8, cutoff_back: u8) -> (usize, usize) {
+    let mut start = 0usize;
+    let mut stop = qual.len();
 
-            let win = window as usize;
-            let thr = qual_thr as u32;
-
-            // find left: first window start i where average >= thr
-            let mut found_left: Option<usize> = None;
-            for i in 0..=n - win {
-                let sum = ps[i + win] - ps[i];
-                if sum / (win as u32) >= thr {
-                    found_left = Some(i);
-                    break;
-                }
-            }
-            if let Some(i) = found_left {
-                start_idx = i;
-            } else {
-                // no qualifying window -> entire read trimmed
-                start_idx = n; // will trigger start_idx > end_idx
-            }
+    let mut s: i64 = 0;
+    let mut max_qual: i64 = 0;
+    for (i, q) in qual.iter().enumerate() {
+        s += cutoff_front as i64 - (*q as i64 - 33);
+        if s < 0 {
+            break;
+        }
+        if s > max_qual {
+            max_qual = s;
+            start = i + 1;
+        }
+    }
 
-            // find right: last window end j where average >= thr -> set right = j
-            let mut found_right: Option<usize> = None;
-            for j in (win - 1)..n {
-                let start = j + 1 - win;
-                let sum = ps[j + 1] - ps[start];
-                if sum / (win as u32) >= thr {
-                    found_right = Some(j);
-                }
-            }
-            if let Some(j) = found_right {
-                end_idx = j;
-            } else {
-                // no qualifying window found; keep right at 0 (left>right will handle drop)
-                end_idx = 0;
-            }
+    let mut s: i64 = 0;
+    let mut max_qual: i64 = 0;
+    for (i, q) in qual.iter().enumerate().rev() {
+        s += cutoff_back as i64 - (*q as i64 - 33);
+        if s < 0 {
+            break;
+        }
+        if s > max_qual {
+            max_qual = s;
+            stop = i;
         }
     }
 
-    if start_idx > end_idx {
-        return None;
+    if start >= stop {
+        (0, 0)
+    } else {
+        (start, stop)
     }
-    let trimmed_len = end_idx - start_idx + 1;
-    if trimmed_len < min_len {
+}
+
+/// Mott-trim a read with 5'/3' cutoffs (as `cutadapt -q <front>,<back>`; a
+/// cutoff of 0 leaves that end alone) and apply `min_len`. As in cutadapt, a read
+/// trimmed away entirely is kept, empty, when `min_len` is 0.
+pub fn trim_record_mott(
+    qual: &[u8],
+    seq: &[u8],
+    cutoff_front: u8,
+    cutoff_back: u8,
+    min_len: usize,
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (start, stop) = mott_trim_index(qual, cutoff_front, cutoff_back);
+    if stop - start < min_len {
         return None;
     }
+    Some((seq[start..stop].to_vec(), qual[start..stop].to_vec()))
+}
 
-    let seq_slice = &seq[start_idx..=end_idx];
-    let qual_slice = &qual[start_idx..=end_idx];
-    Some((seq_slice.to_vec(), qual_slice.to_vec()))
+/// Poly-A/poly-T trimming for RNA-seq and cDNA reads: returns the length of the
+/// 5' poly-T head and of the 3' poly-A tail of `seq` (0 when below `min_len`).
+/// Sparse errors inside the runs are tolerated up to `max_mismatch_rate`; the
+/// tail is measured on what is left after removing the head.
+pub fn trim_poly_a(seq: &[u8], min_len: usize, max_mismatch_rate: f64) -> (usize, usize) {
+    let head = crate::polyx::head_len(seq, b'T', min_len, max_mismatch_rate);
+    let tail = crate::polyx::tail_len(&seq[head..], b'A', min_len, max_mismatch_rate);
+    (head, tail)
+}
+
+fn is_n(b: &u8) -> bool {
+    b.eq_ignore_ascii_case(&b'N')
+}
+
+/// Range of `seq` left after removing leading and trailing N's.
+pub fn trim_n_ends(seq: &[u8]) -> (usize, usize) {
+    let start = seq.iter().position(|b| !is_n(b)).unwrap_or(seq.len());
+    let end = seq.iter().rposition(|b| !is_n(b)).map_or(start, |i| i + 1);
+    (start, end)
+}
+
+/// Range of the longest N-free segment of `seq` (the first one on ties).
+pub fn longest_n_free(seq: &[u8]) -> (usize, usize) {
+    let mut best = (0, 0);
+    let mut seg_start = 0usize;
+    for (i, b) in seq.iter().enumerate() {
+        if is_n(b) {
+            if i - seg_start > best.1 - best.0 {
+                best = (seg_start, i);
+            }
+            seg_start = i + 1;
+        }
+    }
+    if seq.len().saturating_sub(seg_start) > best.1 - best.0 {
+        best = (seg_start, seq.len());
+    }
+    best
 }
 
 #[cfg(test)]
 mod tests {
-    use super::trim_record;
+    use super::{
+        longest_n_free, mask_low_quality, mott_trim_index, trim_n_ends, trim_poly_a, trim_record,
+        trim_record_ends, trim_record_mott, Crop,
+    };
+
+    #[test]
+    fn masks_low_quality_bases_in_place() {
+        let mut seq = b"ACGTNCGT".to_vec();
+        let qual: Vec<u8> = [30u8, 5, 30, 10, 2, 30, 30, 19]
+            .iter()
+            .map(|q| q + 33)
+            .collect();
+        // the existing N is not counted again
+        assert_eq!(mask_low_quality(&mut seq, &qual, 20), 3);
+        assert_eq!(seq, b"ANGNNCGN".to_vec());
+        assert_eq!(mask_low_quality(&mut seq, &qual, 0), 0);
+    }
+
+    #[test]
+    fn separate_front_and_back_thresholds() {
+        // Q15 start, Q30 middle, Q25 tail
+        let mut qual = vec![15u8 + 33; 3];
+        qual.extend(vec![30u8 + 33; 6]);
+        qual.extend(vec![25u8 + 33; 4]);
+        let seq = vec![b'A'; qual.len()];
+
+        let (s, _) = trim_record_ends(&qual, &seq, (20, 1), (28, 1), 1).unwrap();
+        assert_eq!(s.len(), 6);
+        // threshold 0 leaves that end alone
+        let (s, _) = trim_record_ends(&qual, &seq, (0, 1), (28, 1), 1).unwrap();
+        assert_eq!(s.len(), 9);
+        let (s, _) = trim_record_ends(&qual, &seq, (20, 1), (0, 1), 1).unwrap();
+        assert_eq!(s.len(), 10);
+        // windows are set per end too: (15,15,30,30) is the first passing window
+        let (s, _) = trim_record_ends(&qual, &seq, (20, 4), (28, 1), 1).unwrap();
+        assert_eq!(s.len(), 8);
+
+        let (s, _) = trim_record_mott(&qual, &seq, 20, 0, 1).unwrap();
+        assert_eq!(s.len(), 10);
+    }
+
+    #[test]
+    fn crop_head_tail_and_max_len() {
+        let crop = Crop {
+            head: 3,
+            tail: 2,
+            max_len: None,
+        };
+        assert_eq!(crop.range(20), (3, 18));
+        // crops longer than the read leave nothing
+        assert_eq!(crop.range(4), (3, 3));
+        assert_eq!(crop.range(2), (2, 2));
+
+        let capped = Crop {
+            max_len: Some(10),
+            ..crop
+        };
+        assert_eq!(capped.range(20), (3, 13));
+        assert_eq!(capped.range(12), (3, 10));
+        assert_eq!(Crop::default().range(7), (0, 7));
+    }
 
     #[test]
     fn trims_low_ends_and_keeps_middle() {
@@ -188,4 +369,68 @@ mod tests {
             "expected central low-quality region to remain"
         );
     }
+
+    #[test]
+    fn poly_a_tail_and_poly_t_head_lengths() {
+        // noisy poly-A tail: one C among 15 bases
+        let seq = b"ACGTGCTAGCTAGGATCAAAAAAACAAAAAAA";
+        assert_eq!(trim_poly_a(seq, 10, 0.2), (0, 15));
+
+        let seq = b"TTTTTTTTTTTTGCTAGCTAGGATC";
+        assert_eq!(trim_poly_a(seq, 10, 0.2), (12, 0));
+
+        // too short to count as a tail
+        assert_eq!(trim_poly_a(b"ACGTGCTAGCTAGGATCAAAA", 10, 0.2), (0, 0));
+    }
+
+    #[test]
+    fn mott_matches_cutadapt_example() {
+        // example from the cutadapt documentation: -q 10 on
+        // 42, 40, 26, 27, 8, 7, 11, 4, 2, 3 keeps the first four bases
+        let qual: Vec<u8> = [42u8, 40, 26, 27, 8, 7, 11, 4, 2, 3]
+            .iter()
+            .map(|q| q + 33)
+            .collect();
+        assert_eq!(mott_trim_index(&qual, 0, 10), (0, 4));
+        let res = trim_record_mott(&qual, b"ACGTACGTAC", 0, 10, 1).unwrap();
+        assert_eq!(res.0, b"ACGT".to_vec());
+    }
+
+    #[test]
+    fn mott_keeps_empty_reads_without_min_len() {
+        // all below the cutoff: cutadapt writes an empty read
+        let qual = b"#####".to_vec();
+        assert_eq!(mott_trim_index(&qual, 0, 20), (0, 0));
+        assert_eq!(
+            trim_record_mott(&qual, b"ACGTA", 0, 20, 0),
+            Some((Vec::new(), Vec::new()))
+        );
+        assert_eq!(trim_record_mott(&qual, b"ACGTA", 0, 20, 1), None);
+    }
+
+    #[test]
+    fn mott_is_not_stopped_by_single_good_base() {
+        // the edge algorithm stops at the lone Q40; Mott trims through it
+        let qual: Vec<u8> = [40u8, 40, 40, 40, 40, 5, 5, 40, 5, 5]
+            .iter()
+            .map(|q| q + 33)
+            .collect();
+        let seq = b"ACGTACGTAC";
+        assert_eq!(trim_record(&qual, seq, 20, 1, 1).unwrap().0.len(), 8);
+        assert_eq!(mott_trim_index(&qual, 0, 20), (0, 5));
+        // both ends, nothing left
+        assert_eq!(mott_trim_index(&[33 + 2; 4], 20, 20), (0, 0));
+    }
+
+    #[test]
+    fn n_ends_and_longest_n_free_segment() {
+        assert_eq!(trim_n_ends(b"NNACGTNACGNN"), (2, 10));
+        assert_eq!(trim_n_ends(b"ACGT"), (0, 4));
+        let (s, e) = trim_n_ends(b"NNNN");
+        assert_eq!(s, e);
+
+        assert_eq!(longest_n_free(b"ACNACGTNNACG"), (3, 7));
+        assert_eq!(longest_n_free(b"ACGTNACGTTT"), (5, 11));
+        assert_eq!(longest_n_free(b"NNN"), (0, 0));
+    }
 }
@@ -1,6 +1,11 @@
+use assert_cmd::assert::Assert;
 use assert_cmd::Command;
+use bio::io::fastq;
+use flate2::read::MultiGzDecoder;
 use predicates::prelude::*;
 use std::fs;
+use std::io::Read;
+use std::path::Path;
 use std::process::Command as StdCommand;
 use tempfile::tempdir;
 
@@ -180,7 +185,1163 @@ fn gz_and_zstd_outputs_created() -> Result<(), Box<dyn std::error::Error>> {
     assert!(gz_r1.exists());
     assert!(gz_r2.exists());
 
-    // (zstd behavior is tested via helpers and separate error checks)
+    // zstd case: streamed straight to .zst, no intermediate plain files
+    let out_base = td.path().join("out_zst");
+    rustrimmer(&[
+        "--p1",
+        p1.to_str().unwrap(),
+        "--p2",
+        p2.to_str().unwrap(),
+        "--output",
+        out_base.to_str().unwrap(),
+        "--zstd",
+    ])
+    .success();
+    for mate in ["R1", "R2"] {
+        let zst = td.path().join(format!("out_zst_{}.fastq.zst", mate));
+        let gz = td.path().join(format!("out_gz_{}.fastq.gz", mate));
+        let mut gz_text = Vec::new();
+        MultiGzDecoder::new(fs::File::open(gz)?).read_to_end(&mut gz_text)?;
+        assert_eq!(zstd::decode_all(fs::File::open(zst)?)?, gz_text);
+        assert!(!td.path().join(format!("out_zst_{}.fastq", mate)).exists());
+    }
+
+    // zstd outputs are read back as inputs
+    let back = td.path().join("out_back");
+    rustrimmer(&[
+        "--p1",
+        td.path().join("out_zst_R1.fastq.zst").to_str().unwrap(),
+        "--p2",
+        td.path().join("out_zst_R2.fastq.zst").to_str().unwrap(),
+        "--output",
+        back.to_str().unwrap(),
+    ])
+    .success();
+    assert_eq!(
+        read_gz(&td.path().join("out_back_R1.fastq.gz"))?,
+        read_gz(&gz_r1)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn paired_zstd_outputs_read_back() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p1 = td.path().join("z_R1.fastq");
+    let p2 = td.path().join("z_R2.fastq");
+    // one more R2 read than R1 so the singletons file is not empty
+    generate_fastq(&p1, 60, 50)?;
+    generate_fastq(&p2, 60, 51)?;
+
+    let out_base = td.path().join("z");
+    rustrimmer(&[
+        "--p1",
+        p1.to_str().unwrap(),
+        "--p2",
+        p2.to_str().unwrap(),
+        "--output",
+        out_base.to_str().unwrap(),
+        "--zstd",
+    ])
+    .success();
+
+    let read_zst = |name: &str| -> Result<Vec<fastq::Record>, Box<dyn std::error::Error>> {
+        let file = fs::File::open(td.path().join(name))?;
+        let reader = fastq::Reader::new(zstd::Decoder::new(file)?);
+        Ok(reader.records().collect::<Result<_, _>>()?)
+    };
+    let r1 = read_zst("z_R1.fastq.zst")?;
+    let r2 = read_zst("z_R2.fastq.zst")?;
+    let singletons = read_zst("z_singletons.fastq.zst")?;
+    assert!(!r1.is_empty());
+    let ids = |recs: &[fastq::Record]| recs.iter().map(|r| r.id().to_string()).collect::<Vec<_>>();
+    assert_eq!(ids(&r1), ids(&r2));
+    assert_eq!(singletons.last().map(|r| r.id()), Some("SEQ_000051"));
+
+    Ok(())
+}
+
+/// Run rustrimmer with `args`.
+fn rustrimmer(args: &[&str]) -> Assert {
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("rustrimmer"));
+    cmd.args(args).assert()
+}
+
+/// Run rustrimmer with `args`, check that it succeeds and return its result
+/// with the records written to the gzip output `out`.
+fn run_and_read(
+    args: &[&str],
+    out: &Path,
+) -> Result<(Assert, Vec<fastq::Record>), Box<dyn std::error::Error>> {
+    let assert = rustrimmer(args).success();
+    Ok((assert, read_gz(out)?))
+}
+
+/// Records of a gzip-compressed FASTQ output.
+fn read_gz(path: &Path) -> Result<Vec<fastq::Record>, Box<dyn std::error::Error>> {
+    let reader = fastq::Reader::new(MultiGzDecoder::new(fs::File::open(path)?));
+    Ok(reader.records().collect::<Result<_, _>>()?)
+}
+
+/// Write `(id, seq, qual)` records to a FASTQ file.
+fn write_fastq(path: &Path, records: &[(&str, &str, &str)]) -> std::io::Result<()> {
+    let mut text = String::new();
+    for (id, seq, qual) in records {
+        text.push_str(&format!("@{}\n{}\n+\n{}\n", id, seq, qual));
+    }
+    fs::write(path, text)
+}
+
+/// Write `n` random reads of `len` bp from the generator script to `path`.
+fn generate_fastq(path: &Path, len: usize, n: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let gen = format!(
+        "{}/tests/generate_test_fastq.py",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let out = StdCommand::new("python3")
+        .arg(&gen)
+        .args(["--read_length", &len.to_string()])
+        .args(["--number", &n.to_string()])
+        .output()?;
+    fs::write(path, out.stdout)?;
+    Ok(())
+}
+
+#[test]
+fn bgzf_outputs_with_gzi_index() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p1 = td.path().join("b_R1.fastq");
+    let p2 = td.path().join("b_R2.fastq");
+    generate_fastq(&p1, 100, 2000)?;
+    generate_fastq(&p2, 100, 2000)?;
+
+    // --gzi only makes sense with --bgzf
+    rustrimmer(&[
+        "--p1",
+        p1.to_str().unwrap(),
+        "--p2",
+        p2.to_str().unwrap(),
+        "--output",
+        td.path().join("x").to_str().unwrap(),
+        "--gzi",
+    ])
+    .failure();
+
+    let out_base = td.path().join("bgzf");
+    rustrimmer(&[
+        "--p1",
+        p1.to_str().unwrap(),
+        "--p2",
+        p2.to_str().unwrap(),
+        "--output",
+        out_base.to_str().unwrap(),
+        "--bgzf",
+        "--gzi",
+        "--threads",
+        "2",
+    ])
+    .success();
+
+    let raw = fs::read(td.path().join("bgzf_R1.fastq.gz"))?;
+    // BGZF header with its BC extra field, and the 28-byte EOF block
+    assert_eq!(&raw[..4], &[0x1f, 0x8b, 0x08, 0x04]);
+    assert_eq!(&raw[12..14], b"BC");
+    assert_eq!(
+        &raw[raw.len() - 28..raw.len() - 24],
+        &[0x1f, 0x8b, 0x08, 0x04]
+    );
+    let mut text = Vec::new();
+    MultiGzDecoder::new(&raw[..]).read_to_end(&mut text)?;
+    assert!(text.starts_with(b"@"));
+
+    // one index entry per 0xff00-byte block after the first
+    let gzi = fs::read(td.path().join("bgzf_R1.fastq.gz.gzi"))?;
+    let entries = u64::from_le_bytes(gzi[..8].try_into()?);
+    assert_eq!(entries as usize, text.len().div_ceil(0xff00) - 1);
+    assert!(entries > 1);
+    assert_eq!(gzi.len(), 8 + 16 * entries as usize);
+    assert!(td.path().join("bgzf_singletons.fastq.gz.gzi").exists());
+
+    Ok(())
+}
+
+#[test]
+fn output_write_errors_fail_the_run() -> Result<(), Box<dyn std::error::Error>> {
+    if !Path::new("/dev/full").exists() {
+        return Ok(());
+    }
+    let td = tempdir()?;
+    let p = td.path().join("full.fastq");
+    generate_fastq(&p, 50, 100)?;
+
+    // the last compressed block is only written when the output is finished
+    for extra in [&[][..], &["--bgzf"], &["--zstd"]] {
+        let mut args = vec![p.to_str().unwrap(), "--output", "/dev/full"];
+        args.extend(extra);
+        rustrimmer(&args)
+            .failure()
+            .stderr(predicate::str::contains("No space left on device"))
+            .stderr(predicate::str::contains("trimmed kept").not());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn adapter_preset_trims_single_end() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("adapter.fastq");
+    // 40 bp insert followed by the TruSeq R1 adapter, all high quality
+    let insert = "ACGTTGCAACGTTGCAACGTTGCAACGTTGCAACGTTGCA";
+    let seq = format!("{}AGATCGGAAGAGCACACGTCTGAACTCCAGTCA", insert);
+    write_fastq(&p, &[("r1", &seq, &"I".repeat(seq.len()))])?;
+
+    let out_name = td.path().join("out.fastq.gz");
+    let (assert, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--adapter-preset",
+            "truseq",
+        ],
+        &out_name,
+    )?;
+    assert.stdout(predicate::str::contains("adapter trimmed: 1"));
+    assert_eq!(recs[0].seq(), insert.as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn linked_adapter_requires_front() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("linked.fastq");
+    let insert = "ACGTTGCAACGTTGCAACGTTGCAACGTTGCA";
+    // r1 carries both adapters, r2 only the 3' one
+    let r1 = format!("GATTACA{}TGGAATTCTC", insert);
+    let r2 = format!("CCCCCCC{}TGGAATTCTC", insert);
+    let qual = "I".repeat(r1.len());
+    write_fastq(&p, &[("r1", &r1, &qual), ("r2", &r2, &qual)])?;
+
+    let out_name = td.path().join("out.fastq.gz");
+    let (assert, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--adapter",
+            "^GATTACA...TGGAATTCTC",
+        ],
+        &out_name,
+    )?;
+    assert.stdout(predicate::str::contains("adapter trimmed: 1"));
+    let seqs: Vec<&[u8]> = recs.iter().map(|r| r.seq()).collect();
+    assert_eq!(seqs, vec![insert.as_bytes(), r2.as_bytes()]);
+
+    Ok(())
+}
+
+#[test]
+fn crops_are_set_per_mate() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p1 = td.path().join("crop_R1.fastq");
+    let p2 = td.path().join("crop_R2.fastq");
+    let seq = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATGACGTAGCTAG";
+    let qual = "I".repeat(seq.len());
+    write_fastq(&p1, &[("p1/1", seq, &qual)])?;
+    write_fastq(&p2, &[("p1/2", seq, &qual)])?;
+
+    let out_base = td.path().join("crop");
+    let (_, r1) = run_and_read(
+        &[
+            "--p1",
+            p1.to_str().unwrap(),
+            "--p2",
+            p2.to_str().unwrap(),
+            "--output",
+            out_base.to_str().unwrap(),
+            "--min-len",
+            "10",
+            "--head-crop",
+            "3",
+            "--head-crop2",
+            "6",
+            "--tail-crop",
+            "2",
+            "--crop-to2",
+            "30",
+        ],
+        &td.path().join("crop_R1.fastq.gz"),
+    )?;
+    // R1: 3 bases off the 5' end and 2 off the 3' end
+    assert_eq!(r1[0].seq(), &seq.as_bytes()[3..seq.len() - 2]);
+    // R2: its own head crop, the R1 tail crop and a 30 bp cap
+    let r2 = read_gz(&td.path().join("crop_R2.fastq.gz"))?;
+    assert_eq!(r2[0].seq(), &seq.as_bytes()[6..36]);
+
+    Ok(())
+}
+
+#[test]
+fn mott_mode_cuts_like_cutadapt_and_rejects_windows() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("mott.fastq");
+    // example from the cutadapt documentation: -q 10 keeps the first four bases
+    let qual: String = [42u8, 40, 26, 27, 8, 7, 11, 4, 2, 3]
+        .iter()
+        .map(|q| (q + 33) as char)
+        .collect();
+    write_fastq(&p, &[("r1", "ACGTACGTAC", &qual)])?;
+
+    let out_name = td.path().join("mott_out.fastq.gz");
+    let args = [
+        p.to_str().unwrap(),
+        "--output",
+        out_name.to_str().unwrap(),
+        "--trim-mode",
+        "mott",
+        "--qual",
+        "10",
+        "--min-len",
+        "1",
+    ];
+    let (_, recs) = run_and_read(&args, &out_name)?;
+    assert_eq!(recs[0].seq(), b"ACGT");
+
+    for window in ["--window", "--window-back"] {
+        let mut args = args.to_vec();
+        args.extend([window, "4"]);
+        rustrimmer(&args)
+            .failure()
+            .stderr(predicate::str::contains("do not apply to --trim-mode mott"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn mott_mode_output_matches_cutadapt() -> Result<(), Box<dyn std::error::Error>> {
+    // tests/data/mott_q20.cutadapt.fastq is the expected output of
+    // `cutadapt -q 20 -a AGATCGGAAGAGC -o mott_q20.cutadapt.fastq mott_q20.fastq`
+    // (computed with cutadapt's `quality_trim_index`; regenerate it with the
+    // command above to re-check). It covers empty reads, quality trimming before
+    // the adapter and a two-colour poly-G tail that is left alone
+    let data = format!("{}/tests/data", env!("CARGO_MANIFEST_DIR"));
+    let td = tempdir()?;
+    let out_name = td.path().join("mott_q20.fastq.gz");
+    rustrimmer(&[
+        &format!("{}/mott_q20.fastq", data),
+        "--output",
+        out_name.to_str().unwrap(),
+        "--trim-mode",
+        "mott",
+        "--qual",
+        "20",
+        "--min-len",
+        "0",
+        "--adapter",
+        "AGATCGGAAGAGC",
+    ])
+    .success();
+
+    let mut out = String::new();
+    MultiGzDecoder::new(fs::File::open(&out_name)?).read_to_string(&mut out)?;
+    let expected = fs::read_to_string(format!("{}/mott_q20.cutadapt.fastq", data))?;
+    assert_eq!(out, expected);
+
+    Ok(())
+}
+
+#[test]
+fn front_and_back_quality_thresholds() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("ends.fastq");
+    // Q15 start, Q40 middle, Q25 tail
+    let seq = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATG";
+    let qual = format!("{}{}{}", "0".repeat(5), "I".repeat(25), ":".repeat(10));
+    write_fastq(&p, &[("r1", seq, &qual)])?;
+
+    let out_name = td.path().join("out.fastq.gz");
+    let run = |extra: &[&str]| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut args = vec![
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--min-len",
+            "10",
+        ];
+        args.extend(extra);
+        Ok(run_and_read(&args, &out_name)?.1[0].seq().to_vec())
+    };
+
+    // --qual 20 on both ends only removes the Q15 start
+    assert_eq!(run(&[])?, &seq.as_bytes()[5..]);
+    // 3' threshold of 30 also removes the Q25 tail; 5' trimming off keeps the start
+    assert_eq!(
+        run(&["--qual-back", "30", "--qual-front", "0"])?,
+        &seq.as_bytes()[..30]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn mask_qual_replaces_low_bases_with_n() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("mask.fastq");
+    let seq = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATG";
+    // Q2 at positions 10 and 11, Q40 elsewhere
+    let qual = format!("{}##{}", "I".repeat(10), "I".repeat(28));
+    write_fastq(&p, &[("r1", seq, &qual)])?;
+
+    let out_name = td.path().join("out.fastq.gz");
+    let (assert, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--mask-qual",
+            "20",
+        ],
+        &out_name,
+    )?;
+    assert
+        .stdout(predicate::str::contains("masked reads: 1"))
+        .stdout(predicate::str::contains("masked bases: 2"));
+    assert_eq!(
+        recs[0].seq(),
+        format!("{}NN{}", &seq[..10], &seq[12..]).as_bytes()
+    );
+    assert_eq!(recs[0].qual(), qual.as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn phred64_is_detected_and_converted() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("p64.fastq");
+    let seq = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATG";
+    // Phred+64: Q40 ('h') with a Q2 ('B') tail
+    let qual = format!("{}{}", "h".repeat(35), "B".repeat(5));
+    write_fastq(&p, &[("r1", seq, &qual)])?;
+
+    let out_name = td.path().join("out.fastq.gz");
+    let run = |extra: &[&str]| -> Result<fastq::Record, Box<dyn std::error::Error>> {
+        let mut args = vec![p.to_str().unwrap(), "--output", out_name.to_str().unwrap()];
+        args.extend(extra);
+        let (assert, mut recs) = run_and_read(&args, &out_name)?;
+        assert.stdout(predicate::str::contains(
+            "detected quality encoding: Phred+64",
+        ));
+        Ok(recs.remove(0))
+    };
+
+    // the Q2 tail is trimmed and the output stays Phred+64
+    let rec = run(&[])?;
+    assert_eq!(rec.seq(), &seq.as_bytes()[..35]);
+    assert_eq!(rec.qual(), "h".repeat(35).as_bytes());
+
+    let rec = run(&["--to-phred33"])?;
+    assert_eq!(rec.qual(), "I".repeat(35).as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn ambiguous_phred_needs_an_explicit_encoding() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("q42.fastq");
+    let seq = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATG";
+    // filtered Phred+33 from a Q42+ instrument: ';' (Q26) .. 'K' (Q42)
+    let qual = format!("{}{}", "K".repeat(35), ";".repeat(5));
+    write_fastq(&p, &[("r1", seq, &qual)])?;
+    let out_name = td.path().join("out.fastq.gz");
+
+    rustrimmer(&[p.to_str().unwrap(), "--output", out_name.to_str().unwrap()])
+        .failure()
+        .stderr(predicate::str::contains(
+            "cannot tell Phred+33 from Phred+64",
+        ));
+
+    let (_, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--phred",
+            "33",
+        ],
+        &out_name,
+    )?;
+    assert_eq!(recs[0].qual(), qual.as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn phred64_is_detected_on_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let seq = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATG";
+    let qual = format!("{}{}", "h".repeat(35), "B".repeat(5));
+    let out_name = td.path().join("stdin_out.fastq.gz");
+
+    // the sampled reads are still trimmed and written
+    let mut cmd = Command::new(assert_cmd::cargo::cargo_bin!("rustrimmer"));
+    cmd.args(["-", "--output", out_name.to_str().unwrap(), "--to-phred33"])
+        .write_stdin(format!(
+            "@r1\n{}\n+\n{}\n@r2\n{}\n+\n{}\n",
+            seq, qual, seq, qual
+        ))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "detected quality encoding: Phred+64",
+        ));
+    let recs = read_gz(&out_name)?;
+    assert_eq!(recs.len(), 2);
+    assert_eq!(recs[1].seq(), &seq.as_bytes()[..35]);
+    assert_eq!(recs[1].qual(), "I".repeat(35).as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn qual_bins_rewrite_output_qualities() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("bins.fastq");
+    let seq = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATG";
+    // Q40/Q38/Q31/Q21 repeated
+    write_fastq(&p, &[("r1", seq, &"IGB6".repeat(10))])?;
+
+    let out_name = td.path().join("out.fastq.gz");
+    let (assert, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--qual",
+            "0",
+            "--qual-bins",
+            "0-29:20,30-:35",
+        ],
+        &out_name,
+    )?;
+    assert
+        .stdout(predicate::str::contains(
+            "quality bytes sampled: 41 (gzip: ",
+        ))
+        .stdout(predicate::str::contains(
+            "quality size reduction (gzip, sampled): ",
+        ));
+    assert_eq!(recs[0].qual(), "DDD5".repeat(10).as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn detect_overlap_cuts_pairs_at_insert() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p1 = td.path().join("ov_R1.fastq");
+    let p2 = td.path().join("ov_R2.fastq");
+    // 40 bp insert; R2 is its reverse complement, both read into adapters
+    let insert = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATG";
+    let insert_rc = "CATGGATCCGATCGTAAGCCTATCGGACTGCATGCAACGT";
+    let r1 = format!("{}AGATCGGAAGAGCACACGTC", insert);
+    let r2 = format!("{}AGATCGGAAGAGCGTCGTGT", insert_rc);
+    let qual = "I".repeat(r1.len());
+    write_fastq(&p1, &[("p1/1", &r1, &qual)])?;
+    write_fastq(&p2, &[("p1/2", &r2, &qual)])?;
+
+    let out_base = td.path().join("ov");
+    let (assert, recs) = run_and_read(
+        &[
+            "--p1",
+            p1.to_str().unwrap(),
+            "--p2",
+            p2.to_str().unwrap(),
+            "--output",
+            out_base.to_str().unwrap(),
+            "--detect-overlap",
+        ],
+        &td.path().join("ov_R2.fastq.gz"),
+    )?;
+    assert
+        .stdout(predicate::str::contains("insert size median: 40"))
+        .stdout(predicate::str::contains("insert size 40: 1"));
+    assert_eq!(recs[0].seq(), insert_rc.as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn merge_writes_consensus_reads() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p1 = td.path().join("mg_R1.fastq");
+    let p2 = td.path().join("mg_R2.fastq");
+    // 60 bp insert read as 40 bp mates (20 bp overlap) and a pair that does not overlap
+    let insert = "ACGTTGCATGCAGTCCGATAGGCTTACGATCGGATCCATGACTGACCTAGGTCAAGTCCA";
+    let r2_rc: String = insert[20..]
+        .chars()
+        .rev()
+        .map(|c| match c {
+            'A' => 'T',
+            'C' => 'G',
+            'G' => 'C',
+            _ => 'A',
+        })
+        .collect();
+    let other1 = "TTGACCAGTAGCATCGGACTTCAGGCATGCCTAGATCGTA";
+    let other2 = "GGCATTCAGCCTAGCTAGTTACGGATCAGGTCATCCATGA";
+    let qual = "5".repeat(40);
+    write_fastq(
+        &p1,
+        &[("a/1", &insert[..40], &qual), ("b/1", other1, &qual)],
+    )?;
+    write_fastq(&p2, &[("a/2", &r2_rc, &qual), ("b/2", other2, &qual)])?;
+
+    let out_base = td.path().join("mg");
+    let (assert, recs) = run_and_read(
+        &[
+            "--p1",
+            p1.to_str().unwrap(),
+            "--p2",
+            p2.to_str().unwrap(),
+            "--output",
+            out_base.to_str().unwrap(),
+            "--merge",
+            "--overlap-min-len",
+            "15",
+        ],
+        &td.path().join("mg_merged.fastq.gz"),
+    )?;
+    assert
+        .stdout(predicate::str::contains("pairs merged: 1"))
+        .stdout(predicate::str::contains("merge rate: 50.00%"))
+        .stdout(predicate::str::contains("merge overlap 20: 1"))
+        .stdout(predicate::str::contains("pairs kept: 1"));
+    assert_eq!(recs[0].id(), "a/1");
+    assert_eq!(recs[0].seq(), insert.as_bytes());
+    // Q20 + Q20 in the overlap
+    assert_eq!(
+        recs[0].qual(),
+        format!("{}{}{}", "5".repeat(20), "I".repeat(20), "5".repeat(20)).as_bytes()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn threads_give_identical_output() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    // more than one batch of pairs, with an extra R2 read left over
+    let p1 = td.path().join("t_R1.fastq");
+    let p2 = td.path().join("t_R2.fastq");
+    generate_fastq(&p1, 60, 12000)?;
+    generate_fastq(&p2, 60, 12001)?;
+
+    let run = |threads: &str, base: &str| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let out_base = td.path().join(base);
+        let mut bytes = rustrimmer(&[
+            "--p1",
+            p1.to_str().unwrap(),
+            "--p2",
+            p2.to_str().unwrap(),
+            "--output",
+            out_base.to_str().unwrap(),
+            "--max-ee",
+            "2",
+            "--threads",
+            threads,
+        ])
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+        for suffix in ["_R1.fastq.gz", "_R2.fastq.gz", "_singletons.fastq.gz"] {
+            bytes.extend(fs::read(format!("{}{}", out_base.display(), suffix))?);
+        }
+        Ok(bytes)
+    };
+
+    assert!(run("1", "serial")? == run("4", "parallel")?);
+
+    Ok(())
+}
+
+#[test]
+fn adapter_auto_reports_inferred_adapter() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("auto.fastq");
+    generate_fastq(&p, 80, 100)?;
+
+    // splice the Nextera adapter into every other read at varying insert sizes
+    let adapter = "CTGTCTCTTATACACATCT";
+    let mut fastq = String::new();
+    for (i, line) in fs::read_to_string(&p)?.lines().enumerate() {
+        let rec = i / 4;
+        if i % 4 == 1 && rec % 2 == 0 {
+            let at = 30 + rec % 7;
+            fastq.push_str(&line[..at]);
+            fastq.push_str(adapter);
+            fastq.push_str(&line[at + adapter.len()..]);
+        } else {
+            fastq.push_str(line);
+        }
+        fastq.push('\n');
+    }
+    fs::write(&p, fastq)?;
+
+    let out_name = td.path().join("auto_out.fastq.gz");
+    rustrimmer(&[
+        p.to_str().unwrap(),
+        "--output",
+        out_name.to_str().unwrap(),
+        "--adapter-auto",
+    ])
+    .success()
+    .stdout(predicate::str::contains(
+        "inferred adapter: CTGTCTCTTATACACATCT (supported by 50 of 100 sampled reads)",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn poly_g_trimmed_for_two_colour_headers() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("novaseq.fastq");
+    let insert = "ACGTTGCAACGTTGCAACGTTGCAACGTTGCAACGTTGCA";
+    let seq = format!("{}{}", insert, "G".repeat(20));
+    let qual = "I".repeat(seq.len());
+    write_fastq(
+        &p,
+        &[
+            ("A00123:8:HFWLKDSXX:1:1101:1000:1000 1:N:0:1", &seq, &qual),
+            (
+                "M01234:12:000000000-AXXXX:1:1101:100:100 1:N:0:1",
+                &seq,
+                &qual,
+            ),
+        ],
+    )?;
+
+    // only the NovaSeq read is trimmed; the MiSeq read keeps its G run
+    let out_name = td.path().join("novaseq_out.fastq.gz");
+    rustrimmer(&[p.to_str().unwrap(), "--output", out_name.to_str().unwrap()])
+        .success()
+        .stdout(predicate::str::contains("poly-G trimmed: 1"));
+
+    // no two-colour reads: the line is left out of the summary
+    let miseq = td.path().join("miseq.fastq");
+    write_fastq(
+        &miseq,
+        &[(
+            "M01234:12:000000000-AXXXX:1:1101:100:100 1:N:0:1",
+            &seq,
+            &qual,
+        )],
+    )?;
+    rustrimmer(&[
+        miseq.to_str().unwrap(),
+        "--output",
+        out_name.to_str().unwrap(),
+    ])
+    .success()
+    .stdout(predicate::str::contains("poly-G").not());
+
+    Ok(())
+}
+
+#[test]
+fn poly_a_tag_records_tail_length() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("polya.fastq");
+    let insert = "ACGTTGCAACGTTGCAACGTTGCAACGTTGCAACGTTGCC";
+    let seq = format!("{}{}", insert, "A".repeat(18));
+    write_fastq(&p, &[("tx1 sample=a", &seq, &"I".repeat(seq.len()))])?;
+
+    let out_name = td.path().join("polya_out.fastq.gz");
+    let (assert, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--poly-a",
+            "--poly-a-tag",
+        ],
+        &out_name,
+    )?;
+    assert.stdout(predicate::str::contains("poly-A bases removed: 18"));
+    assert_eq!(recs[0].desc(), Some("sample=a pA:i:18 pT:i:0"));
+    assert_eq!(recs[0].seq(), insert.as_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn invalid_steps_string_errors() -> Result<(), Box<dyn std::error::Error>> {
+    rustrimmer(&[
+        "in.fastq",
+        "--output",
+        "out.fastq.gz",
+        "--steps",
+        "LEADING:3 ILLUMINACLIP:adapters.fa:2:30:10",
+    ])
+    .failure()
+    .stderr(predicate::str::contains("unsupported step"));
+    Ok(())
+}
+
+#[test]
+fn max_ee_drops_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("ee.fastq");
+    let seq = "ACGT".repeat(10);
+    // Q40 everywhere vs. Q20 everywhere (EE 0.004 vs 0.4)
+    write_fastq(
+        &p,
+        &[
+            ("good", &seq, &"I".repeat(40)),
+            ("fair", &seq, &"5".repeat(40)),
+        ],
+    )?;
+
+    let out_name = td.path().join("ee_out.fastq.gz");
+    rustrimmer(&[
+        p.to_str().unwrap(),
+        "--output",
+        out_name.to_str().unwrap(),
+        "--max-ee",
+        "0.1",
+    ])
+    .success()
+    .stdout(predicate::str::contains("dropped (max EE): 1"))
+    .stderr(predicate::str::contains("trimmed kept: 1  dropped: 1"));
+
+    Ok(())
+}
+
+#[test]
+fn mean_and_unqualified_quality_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("mq.fastq");
+    let seq = "ACGT".repeat(10);
+    // Q40 everywhere; Q22 everywhere (mean 22); alternating Q40/Q10 (mean 25, 50% below Q15)
+    write_fastq(
+        &p,
+        &[
+            ("good", &seq, &"I".repeat(40)),
+            ("low_mean", &seq, &"7".repeat(40)),
+            ("spotty", &seq, &"I+".repeat(20)),
+        ],
+    )?;
+
+    let out_name = td.path().join("mq_out.fastq.gz");
+    rustrimmer(&[
+        p.to_str().unwrap(),
+        "--output",
+        out_name.to_str().unwrap(),
+        "--qual",
+        "0",
+        "--min-mean-qual",
+        "25",
+        "--max-unqualified-percent",
+        "40",
+    ])
+    .success()
+    .stdout(predicate::str::contains("dropped (mean quality): 1"))
+    .stdout(predicate::str::contains("dropped (unqualified bases): 1"))
+    .stderr(predicate::str::contains("trimmed kept: 1  dropped: 2"));
+
+    Ok(())
+}
+
+#[test]
+fn length_bins_split_outputs_and_max_len() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("len.fastq");
+    let seq = "ACGT".repeat(15);
+    let qual = "I".repeat(60);
+    write_fastq(
+        &p,
+        &[
+            ("a", &seq[..20], &qual[..20]),
+            ("b", &seq[..22], &qual[..22]),
+            ("c", &seq[..35], &qual[..35]),
+            ("d", &seq, &qual),
+        ],
+    )?;
+
+    let out_name = td.path().join("len.fastq.gz");
+    let (assert, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--min-len",
+            "15",
+            "--max-len",
+            "50",
+            "--length-bins",
+            "18,30",
+        ],
+        &td.path().join("len_len18-29.fastq.gz"),
+    )?;
+    assert
+        .stdout(predicate::str::contains("dropped (max length): 1"))
+        .stdout(predicate::str::contains("length 0-17: 0"))
+        .stdout(predicate::str::contains("length 18-29: 2"))
+        .stdout(predicate::str::contains("length 30+: 1"));
+    let ids: Vec<&str> = recs.iter().map(|r| r.id()).collect();
+    assert_eq!(ids, vec!["a", "b"]);
+
+    Ok(())
+}
+
+#[test]
+fn max_len_checks_the_ee_truncated_length() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("ee_len.fastq");
+    let seq = "ACGT".repeat(15);
+    // Q40 x 40 then Q2 x 20: EE truncation cuts the read to 41 bp
+    write_fastq(
+        &p,
+        &[("r1", &seq, &format!("{}{}", "I".repeat(40), "#".repeat(20)))],
+    )?;
+
+    let out_name = td.path().join("ee_len_out.fastq.gz");
+    let (assert, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--qual",
+            "0",
+            "--truncate-ee",
+            "1",
+            "--max-len",
+            "50",
+        ],
+        &out_name,
+    )?;
+    assert.stdout(predicate::str::contains("dropped (max length): 0"));
+    assert_eq!(recs[0].seq(), &seq.as_bytes()[..41]);
+
+    Ok(())
+}
+
+#[test]
+fn n_handling_counts_each_drop_reason() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("n.fastq");
+    let qual = "I".repeat(40);
+    write_fastq(
+        &p,
+        &[
+            ("clean", &"ACGT".repeat(10), &qual),
+            ("ends", &format!("NN{}NN", "ACGT".repeat(9)), &qual),
+            ("many", &"ACGTN".repeat(8), &qual),
+            ("alln", &"N".repeat(40), &qual),
+        ],
+    )?;
+
+    let out_name = td.path().join("n_out.fastq.gz");
+    rustrimmer(&[
+        p.to_str().unwrap(),
+        "--output",
+        out_name.to_str().unwrap(),
+        "--trim-n",
+        "--max-n",
+        "3",
+    ])
+    .success()
+    .stdout(predicate::str::contains("N ends trimmed: 3"))
+    .stdout(predicate::str::contains("dropped (all N): 1"))
+    .stdout(predicate::str::contains("dropped (max N): 1"))
+    .stderr(predicate::str::contains("trimmed kept: 2  dropped: 2"));
+
+    Ok(())
+}
+
+#[test]
+fn n_drops_are_attributed_to_their_stage() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p = td.path().join("n_stage.fastq");
+    let qual = "I".repeat(40);
+    write_fastq(&p, &[("r1", &"ACGT".repeat(10), &qual)])?;
+
+    // a read cropped away entirely is not counted as all-N
+    let out_name = td.path().join("n_stage_out.fastq.gz");
+    rustrimmer(&[
+        p.to_str().unwrap(),
+        "--output",
+        out_name.to_str().unwrap(),
+        "--head-crop",
+        "50",
+        "--trim-n",
+    ])
+    .success()
+    .stdout(predicate::str::contains("dropped (all N): 0"))
+    .stderr(predicate::str::contains("trimmed kept: 0  dropped: 1"));
+
+    // under `--steps` a split segment only has to pass its MINLEN, not `--min-len`
+    let split = td.path().join("n_split.fastq");
+    let seq = format!("{}N{}", "ACGT".repeat(5), "ACG".repeat(6));
+    write_fastq(&split, &[("r1", &seq, &"I".repeat(seq.len()))])?;
+    let (assert, recs) = run_and_read(
+        &[
+            split.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--n-split",
+            "--steps",
+            "MINLEN:10",
+        ],
+        &out_name,
+    )?;
+    assert.stdout(predicate::str::contains("dropped (N split too short): 0"));
+    assert_eq!(recs[0].seq(), &seq.as_bytes()[..20]);
+
+    Ok(())
+}
+
+#[test]
+fn complexity_pair_modes() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let p1 = td.path().join("lc_R1.fastq");
+    let p2 = td.path().join("lc_R2.fastq");
+    let qual = "I".repeat(60);
+    // R1 is a dinucleotide repeat, R2 looks normal
+    write_fastq(&p1, &[("lc/1", &"CA".repeat(30), &qual)])?;
+    write_fastq(
+        &p2,
+        &[(
+            "lc/2",
+            "ACGGTCATTGCAGTCCGATAGGCTTACGATCGGATCCATGACTGACTTAGCCATGCAATC",
+            &qual,
+        )],
+    )?;
+
+    for (mode, expected) in [("either", "pairs dropped: 1"), ("both", "pairs kept: 1")] {
+        let out_base = td.path().join(format!("lc_{}", mode));
+        rustrimmer(&[
+            "--p1",
+            p1.to_str().unwrap(),
+            "--p2",
+            p2.to_str().unwrap(),
+            "--output",
+            out_base.to_str().unwrap(),
+            "--complexity",
+            "entropy",
+            "--complexity-pair",
+            mode,
+        ])
+        .success()
+        .stdout(predicate::str::contains(expected));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn primers_removed_and_unmatched_pairs_separated() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let fasta = td.path().join("primers.fa");
+    fs::write(
+        &fasta,
+        ">V4_F\nGTGYCAGCMGCCGCGGTAA\n>V4_R\nGGACTACNVGGGTWTCTAAT\n",
+    )?;
+    let p1 = td.path().join("amp_R1.fastq");
+    let p2 = td.path().join("amp_R2.fastq");
+    let amplicon1 = "TACGGAGGGTGCAAGCGTTAATCGGAATTACTGGGCGTAAAG";
+    let amplicon2 = "CCTGTTTGCTCCCCACGCTTTCGCACCTCAGCGTCAGTTACA";
+    let r1 = format!("GTGCCAGCAGCCGCGGTAA{}", amplicon1);
+    let r2 = format!("GGACTACCAGGGTATCTAAT{}", amplicon2);
+    let qual = "I".repeat(r2.len());
+    write_fastq(
+        &p1,
+        &[
+            ("a/1", &r1, &qual[..r1.len()]),
+            ("b/1", amplicon1, &qual[..42]),
+        ],
+    )?;
+    write_fastq(&p2, &[("a/2", &r2, &qual), ("b/2", amplicon2, &qual[..42])])?;
+
+    let out_base = td.path().join("amp");
+    let noprimer_base = td.path().join("amp_noprimer");
+    let (assert, recs) = run_and_read(
+        &[
+            "--p1",
+            p1.to_str().unwrap(),
+            "--p2",
+            p2.to_str().unwrap(),
+            "--output",
+            out_base.to_str().unwrap(),
+            "--primers",
+            fasta.to_str().unwrap(),
+            "--no-primer-output",
+            noprimer_base.to_str().unwrap(),
+        ],
+        &td.path().join("amp_R1.fastq.gz"),
+    )?;
+    assert
+        .stdout(predicate::str::contains("primer V4: 1"))
+        .stdout(predicate::str::contains("no primer: 1"))
+        .stdout(predicate::str::contains("pairs kept: 1"));
+    assert_eq!(recs[0].id(), "a/1");
+    assert_eq!(recs[0].desc(), Some("pr:Z:V4"));
+    assert_eq!(recs[0].seq(), amplicon1.as_bytes());
+
+    let unmatched = read_gz(&td.path().join("amp_noprimer_R2.fastq.gz"))?;
+    assert_eq!(unmatched[0].id(), "b/2");
+
+    Ok(())
+}
+
+#[test]
+fn no_primer_output_uses_output_quality_encoding() -> Result<(), Box<dyn std::error::Error>> {
+    let td = tempdir()?;
+    let fasta = td.path().join("primers.fa");
+    fs::write(
+        &fasta,
+        ">V4_F\nGTGYCAGCMGCCGCGGTAA\n>V4_R\nGGACTACNVGGGTWTCTAAT\n",
+    )?;
+    let p = td.path().join("amp64.fastq");
+    let amplicon = "TACGGAGGGTGCAAGCGTTAATCGGAATTACTGGGCGTAAAG";
+    let primed = format!("GTGCCAGCAGCCGCGGTAA{}", amplicon);
+    // Phred+64 Q40 throughout
+    write_fastq(
+        &p,
+        &[
+            ("a", &primed, &"h".repeat(primed.len())),
+            ("b", amplicon, &"h".repeat(amplicon.len())),
+        ],
+    )?;
+
+    let out_name = td.path().join("amp64.fastq.gz");
+    let noprimer_name = td.path().join("amp64_noprimer.fastq.gz");
+    let (_, recs) = run_and_read(
+        &[
+            p.to_str().unwrap(),
+            "--output",
+            out_name.to_str().unwrap(),
+            "--primers",
+            fasta.to_str().unwrap(),
+            "--no-primer-output",
+            noprimer_name.to_str().unwrap(),
+            "--to-phred33",
+        ],
+        &out_name,
+    )?;
+    assert_eq!(recs[0].qual(), "I".repeat(amplicon.len()).as_bytes());
+
+    let unmatched = read_gz(&noprimer_name)?;
+    assert_eq!(unmatched[0].id(), "b");
+    assert_eq!(unmatched[0].seq(), amplicon.as_bytes());
+    assert_eq!(unmatched[0].qual(), "I".repeat(amplicon.len()).as_bytes());
 
     Ok(())
 }